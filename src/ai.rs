@@ -1,3 +1,4 @@
+#[cfg(feature = "ai")]
 use std::path::Path;
 
 #[cfg(feature = "ai")]
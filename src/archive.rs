@@ -0,0 +1,734 @@
+use crate::cache::mtime_ns;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Archive formats understood by the `archive` and `extract` actions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl Format {
+    /// Picks the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Format::TarZst)
+        } else {
+            None
+        }
+    }
+
+    /// Picks the format from the extension, falling back to the magic bytes
+    /// for downloads with an unhelpful name.
+    #[cfg_attr(not(feature = "archive"), allow(dead_code))]
+    pub fn detect(path: &Path) -> Option<Self> {
+        if let Some(format) = Self::from_path(path) {
+            return Some(format);
+        }
+        let mut header = [0u8; 512];
+        let n = std::io::Read::read(&mut std::fs::File::open(path).ok()?, &mut header).ok()?;
+        let header = &header[..n];
+        if header.starts_with(b"PK\x03\x04") {
+            Some(Format::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::TarGz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::TarZst)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(Format::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Safety limits for extraction, checked against the bytes actually written
+/// rather than the sizes an archive claims.
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+pub struct ExtractLimits {
+    /// Total uncompressed bytes
+    pub max_bytes: u64,
+    pub max_entries: usize,
+    /// Uncompressed size relative to the archive size
+    pub max_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 4 * 1024 * 1024 * 1024,
+            max_entries: 100_000,
+            max_ratio: 200,
+        }
+    }
+}
+
+/// Member names of the archives written during one run, so that adding many
+/// files to the same archive does not read it back every time. An archive
+/// that changed since it was cached (size or modification time) is read again.
+#[derive(Default)]
+pub struct MemberCache {
+    archives: Mutex<HashMap<PathBuf, CachedMembers>>,
+}
+
+struct CachedMembers {
+    stamp: Option<(u64, i64)>,
+    names: HashSet<String>,
+}
+
+impl MemberCache {
+    /// True if `archive` already holds a member called `name`.
+    pub fn contains(&self, archive: &Path, name: &str) -> anyhow::Result<bool> {
+        self.with_names(archive, |names| Ok(names.contains(name)))
+    }
+
+    /// Runs `f` on the member names of `archive`; `f` may write to the
+    /// archive and is expected to record the members it added.
+    fn with_names<T>(&self, archive: &Path, f: impl FnOnce(&mut HashSet<String>) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut archives = self.archives.lock().unwrap();
+        let current = stamp(archive);
+        if archives.get(archive).is_none_or(|cached| cached.stamp != current) {
+            let names = members(archive)?.into_iter().collect();
+            archives.insert(archive.to_path_buf(), CachedMembers { stamp: current, names });
+        }
+        let cached = archives.get_mut(archive).unwrap();
+        let result = f(&mut cached.names);
+        cached.stamp = stamp(archive);
+        result
+    }
+}
+
+fn stamp(archive: &Path) -> Option<(u64, i64)> {
+    let metadata = std::fs::metadata(archive).ok()?;
+    Some((metadata.len(), mtime_ns(&metadata)))
+}
+
+/// Splits a member name into its stem and extension.
+fn split_name(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (name, None),
+    }
+}
+
+/// True if `member` is a name `append` could have given a file called
+/// `file_name`: the name itself, or "stem (i).ext" after a clash.
+pub fn named_after(member: &str, file_name: &str) -> bool {
+    if member == file_name {
+        return true;
+    }
+    let (stem, ext) = split_name(file_name);
+    let rest = match ext {
+        Some(ext) => member.strip_suffix(ext).and_then(|rest| rest.strip_suffix('.')),
+        None => Some(member),
+    };
+    rest.and_then(|rest| rest.strip_prefix(stem))
+        .and_then(|rest| rest.strip_prefix(" ("))
+        .and_then(|rest| rest.strip_suffix(')'))
+        .is_some_and(|i| !i.is_empty() && i.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(feature = "archive")]
+mod imp {
+    use super::{split_name, ExtractLimits, Format, MemberCache};
+    use crate::config::ConflictStrategy;
+    use anyhow::Context;
+    use chrono::{DateTime, Datelike, Local, Timelike};
+    use sha2::{Digest, Sha256};
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::collections::HashSet;
+    use std::path::{Component, Path, PathBuf};
+
+    /// Below this many extracted bytes the compression ratio is not checked,
+    /// since tiny archives of text routinely exceed it.
+    const RATIO_FLOOR: u64 = 16 * 1024 * 1024;
+
+    fn format_of(archive: &Path) -> anyhow::Result<Format> {
+        Format::from_path(archive)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported archive type {:?} (use .zip, .tar, .tar.gz or .tar.zst)", archive))
+    }
+
+    fn tar_reader(format: Format, file: File) -> anyhow::Result<Box<dyn Read>> {
+        Ok(match format {
+            Format::Tar => Box::new(file),
+            Format::TarGz => Box::new(flate2::read::MultiGzDecoder::new(file)),
+            Format::TarZst => Box::new(zstd::Decoder::new(file)?),
+            Format::Zip => anyhow::bail!("Not a tar archive"),
+        })
+    }
+
+    /// Names of all members currently stored in `archive` (empty if it does not exist yet).
+    pub fn members(archive: &Path) -> anyhow::Result<Vec<String>> {
+        if !archive.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(archive).with_context(|| format!("Failed to open {:?}", archive))?;
+        let names = match format_of(archive)? {
+            Format::Zip => zip::ZipArchive::new(file)?.file_names().map(String::from).collect(),
+            format => {
+                let mut tar = tar::Archive::new(tar_reader(format, file)?);
+                tar.set_ignore_zeros(true);
+                let mut names = Vec::new();
+                for entry in tar.entries()? {
+                    names.push(entry?.path()?.to_string_lossy().to_string());
+                }
+                names
+            }
+        };
+        Ok(names)
+    }
+
+    /// Adds `source` to `archive` (creating it if needed) under a member name
+    /// that does not clash with existing members, then reads the member back
+    /// and compares it with the source. If anything fails the archive is put
+    /// back as it was. Returns the member name.
+    pub fn append(archive: &Path, source: &Path, cache: &MemberCache) -> anyhow::Result<String> {
+        let format = format_of(archive)?;
+        let file_name = source
+            .file_name()
+            .context("Cannot archive a path without a file name")?
+            .to_string_lossy();
+
+        cache.with_names(archive, |names| {
+            let member = unique_member(names, &file_name);
+            let saved = SavedEnd::read(format, archive).with_context(|| format!("Failed to read {:?}", archive))?;
+            if let Err(e) = write_verified(format, archive, source, &member, saved.tail_start) {
+                // Drop the partial write so the archive stays readable
+                saved
+                    .restore(archive)
+                    .with_context(|| format!("Failed to add {:?} and to roll {:?} back ({:#})", source, archive, e))?;
+                return Err(e.context(format!("Failed to add {:?} to {:?}", source, archive)));
+            }
+            names.insert(member.clone());
+            Ok(member)
+        })
+    }
+
+    /// Writes the member and reads it back. `end` is where the archive's own
+    /// data ends: a plain tar written by another tool is cut there, before its
+    /// end-of-archive blocks.
+    fn write_verified(format: Format, archive: &Path, source: &Path, member: &str, end: u64) -> anyhow::Result<()> {
+        let offset = match format {
+            Format::Zip => append_zip(archive, source, member)?,
+            format => append_tar(format, archive, source, member, end)?,
+        };
+
+        let expected = sha256(File::open(source)?)?;
+        let stored = match format {
+            Format::Zip => {
+                let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+                let entry = zip.by_name(member)?;
+                sha256(entry)?
+            }
+            format => {
+                // Only decode what was written above
+                let mut file = File::open(archive)?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut tar = tar::Archive::new(tar_reader(format, file)?);
+                let entry = tar.entries()?.next().context("Appended member is missing")??;
+                sha256(entry)?
+            }
+        };
+        if stored != expected {
+            anyhow::bail!("Verification of {:?} in {:?} failed: content differs from the source", member, archive);
+        }
+        Ok(())
+    }
+
+    /// The end of an archive as it was before an append: its length and,
+    /// for zip files, the central directory that appending overwrites, or
+    /// for plain tar files the end-of-archive blocks that it cuts off.
+    struct SavedEnd {
+        existed: bool,
+        length: u64,
+        tail_start: u64,
+        tail: Vec<u8>,
+    }
+
+    impl SavedEnd {
+        fn read(format: Format, archive: &Path) -> anyhow::Result<Self> {
+            let mut file = match File::open(archive) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(Self { existed: false, length: 0, tail_start: 0, tail: Vec::new() });
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let length = file.metadata()?.len();
+            let tail_start = match format {
+                Format::Zip if length > 0 => zip::ZipArchive::new(&mut file)?.central_directory_start(),
+                Format::Tar => end_marker(&mut file)?.unwrap_or(length),
+                _ => length,
+            };
+            let mut tail = Vec::new();
+            file.seek(SeekFrom::Start(tail_start))?;
+            file.read_to_end(&mut tail)?;
+            Ok(Self { existed: true, length, tail_start, tail })
+        }
+
+        fn restore(&self, archive: &Path) -> io::Result<()> {
+            if !self.existed {
+                return std::fs::remove_file(archive);
+            }
+            let mut file = OpenOptions::new().write(true).open(archive)?;
+            file.seek(SeekFrom::Start(self.tail_start))?;
+            file.write_all(&self.tail)?;
+            file.set_len(self.length)?;
+            file.sync_all()
+        }
+    }
+
+    /// Picks the first free member name: "name", then "stem (1).ext", ...
+    fn unique_member(existing: &HashSet<String>, name: &str) -> String {
+        let (stem, ext) = split_name(name);
+        let mut candidate = name.to_string();
+        let mut i = 1;
+        while existing.contains(&candidate) {
+            candidate = match ext {
+                Some(ext) => format!("{} ({}).{}", stem, i, ext),
+                None => format!("{} ({})", stem, i),
+            };
+            i += 1;
+        }
+        candidate
+    }
+
+    fn append_zip(archive: &Path, source: &Path, member: &str) -> anyhow::Result<u64> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(archive)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut zip = if is_new { zip::ZipWriter::new(file) } else { zip::ZipWriter::new_append(file)? };
+
+        let metadata = std::fs::metadata(source)?;
+        let mut options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(metadata.len() >= u32::MAX as u64);
+        if let Some(time) = metadata.modified().ok().and_then(zip_time) {
+            options = options.last_modified_time(time);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            options = options.unix_permissions(metadata.permissions().mode());
+        }
+
+        zip.start_file(member, options)?;
+        io::copy(&mut File::open(source)?, &mut zip)?;
+        zip.finish()?.sync_all()?;
+        Ok(0)
+    }
+
+    fn zip_time(time: std::time::SystemTime) -> Option<zip::DateTime> {
+        let local: DateTime<Local> = time.into();
+        zip::DateTime::from_date_and_time(
+            local.year().try_into().ok()?,
+            local.month() as u8,
+            local.day() as u8,
+            local.hour() as u8,
+            local.minute() as u8,
+            local.second() as u8,
+        )
+        .ok()
+    }
+
+    /// Appends the member at the end of the file. For compressed tarballs it
+    /// becomes a new gzip member or zstd frame; consecutive ones decode as a
+    /// single stream, so the archive stays a plain `.tar.gz`/`.tar.zst`. The
+    /// tar end-of-archive blocks are left out so that later members remain
+    /// visible to tools that stop at the first end marker. A plain tar that
+    /// has them is cut at `end` first; a compressed one cannot be cut, so
+    /// appending to it is refused.
+    /// Returns the offset at which the new data starts.
+    fn append_tar(format: Format, archive: &Path, source: &Path, member: &str, end: u64) -> anyhow::Result<u64> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(archive)?;
+        if format == Format::Tar {
+            file.set_len(end)?;
+        } else if end > 0 && end_marker(tar_reader(format, file.try_clone()?)?)?.is_some() {
+            anyhow::bail!(
+                "{:?} was not written by rarch and ends in an end-of-archive marker that would hide new members; \
+                 archive to a new file instead",
+                archive
+            );
+        }
+        let offset = file.seek(SeekFrom::End(0))?;
+        let out = match format {
+            Format::TarGz => {
+                let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+                write_tar_member(encoder, source, member)?.finish()?
+            }
+            Format::TarZst => write_tar_member(zstd::Encoder::new(file, 0)?, source, member)?.finish()?,
+            _ => write_tar_member(file, source, member)?,
+        };
+        out.sync_all()?;
+        Ok(offset)
+    }
+
+    /// Offset of the first end-of-archive block in a tar stream, found by
+    /// walking its headers. `None` if the stream ends without one.
+    fn end_marker<R: Read>(mut reader: R) -> anyhow::Result<Option<u64>> {
+        let mut offset = 0;
+        let mut block = [0u8; 512];
+        loop {
+            let mut filled = 0;
+            while filled < block.len() {
+                match reader.read(&mut block[filled..])? {
+                    0 if filled == 0 => return Ok(None),
+                    0 => anyhow::bail!("tar stream ends inside a header"),
+                    n => filled += n,
+                }
+            }
+            if block.iter().all(|b| *b == 0) {
+                return Ok(Some(offset));
+            }
+            let size = tar::Header::from_byte_slice(&block).entry_size()?;
+            let padded = size.div_ceil(512) * 512;
+            let skipped = io::copy(&mut (&mut reader).take(padded), &mut io::sink())?;
+            anyhow::ensure!(skipped == padded, "tar stream ends inside a member");
+            offset += 512 + padded;
+        }
+    }
+
+    fn write_tar_member<W: Write>(out: W, source: &Path, member: &str) -> anyhow::Result<W> {
+        let mut builder = tar::Builder::new(Unterminated { inner: out, closed: false });
+        builder.append_path_with_name(source, member)?;
+        builder.get_mut().closed = true;
+        Ok(builder.into_inner()?.inner)
+    }
+
+    /// Writer that silently drops everything once `closed` is set, used to
+    /// keep `tar::Builder` from writing its end-of-archive blocks.
+    struct Unterminated<W: Write> {
+        inner: W,
+        closed: bool,
+    }
+
+    impl<W: Write> Write for Unterminated<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.closed {
+                return Ok(buf.len());
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    /// Restores a single member of `archive` to `dest`.
+    pub fn extract_member(archive: &Path, member: &str, dest: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::open(archive).with_context(|| format!("Failed to open {:?}", archive))?;
+        match format_of(archive)? {
+            Format::Zip => {
+                let mut zip = zip::ZipArchive::new(file)?;
+                let mut entry = zip.by_name(member)?;
+                let mut out = File::create(dest)?;
+                io::copy(&mut entry, &mut out)?;
+                #[cfg(unix)]
+                if let Some(mode) = entry.unix_mode() {
+                    use std::os::unix::fs::PermissionsExt;
+                    out.set_permissions(std::fs::Permissions::from_mode(mode))?;
+                }
+            }
+            format => {
+                let mut tar = tar::Archive::new(tar_reader(format, file)?);
+                tar.set_ignore_zeros(true);
+                let mut found = false;
+                for entry in tar.entries()? {
+                    let mut entry = entry?;
+                    if entry.path()?.to_string_lossy() == member {
+                        entry.unpack(dest)?;
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    anyhow::bail!("{:?} has no member named {:?}", archive, member);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpacks `archive` into `dest` and returns what it created.
+    ///
+    /// Entries whose path is absolute or climbs out of `dest` are rejected,
+    /// links and special files are never created, existing files are never
+    /// replaced, and extraction stops once `limits` are exceeded. On any
+    /// error everything extracted so far is removed again. `on_create` is
+    /// called with every file and directory right before it is created.
+    pub fn extract(
+        archive: &Path,
+        dest: &Path,
+        conflict: &ConflictStrategy,
+        limits: &ExtractLimits,
+        on_create: &mut dyn FnMut(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Extracted> {
+        let format = Format::detect(archive).with_context(|| format!("{:?} is not a supported archive", archive))?;
+        let compressed = std::fs::metadata(archive)?.len();
+        let mut extraction = Extraction {
+            // Set to the canonical path once `dest` exists
+            dest: PathBuf::new(),
+            conflict,
+            limits,
+            max_ratio_bytes: compressed.saturating_mul(limits.max_ratio).max(RATIO_FLOOR),
+            written: 0,
+            entries: 0,
+            created: Extracted::default(),
+            on_create,
+        };
+
+        let result = extraction.create_dirs(dest).and_then(|_| {
+            extraction.dest = dest.canonicalize()?;
+            extraction.run(format, archive)
+        });
+        match result {
+            Ok(()) => Ok(extraction.created),
+            Err(e) => {
+                extraction.created.remove();
+                Err(e.context(format!("Failed to extract {:?}", archive)))
+            }
+        }
+    }
+
+    /// Files and directories created by an extraction.
+    #[derive(Debug, Default)]
+    pub struct Extracted {
+        pub files: Vec<PathBuf>,
+        /// Directories that did not exist before, parents first
+        pub dirs: Vec<PathBuf>,
+    }
+
+    impl Extracted {
+        /// Removes everything that was extracted, leaving the file system as it was.
+        pub fn remove(&self) {
+            for file in self.files.iter().rev() {
+                let _ = std::fs::remove_file(file);
+            }
+            for dir in self.dirs.iter().rev() {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+    }
+
+    struct Extraction<'a> {
+        dest: PathBuf,
+        conflict: &'a ConflictStrategy,
+        limits: &'a ExtractLimits,
+        max_ratio_bytes: u64,
+        written: u64,
+        entries: usize,
+        created: Extracted,
+        on_create: &'a mut dyn FnMut(&Path) -> anyhow::Result<()>,
+    }
+
+    impl Extraction<'_> {
+        fn run(&mut self, format: Format, archive: &Path) -> anyhow::Result<()> {
+            let file = File::open(archive)?;
+            if format == Format::Zip {
+                let mut zip = zip::ZipArchive::new(file)?;
+                for i in 0..zip.len() {
+                    let entry = zip.by_index(i)?;
+                    self.count_entry()?;
+                    let name = entry.name().to_string();
+                    if entry.is_dir() {
+                        self.dir(&name)?;
+                    } else if !entry.is_symlink() {
+                        let mode = entry.unix_mode();
+                        self.file(&name, entry, mode)?;
+                    }
+                }
+                return Ok(());
+            }
+
+            let mut tar = tar::Archive::new(tar_reader(format, file)?);
+            tar.set_ignore_zeros(true);
+            for entry in tar.entries()? {
+                let entry = entry?;
+                self.count_entry()?;
+                let name = entry.path()?.to_string_lossy().to_string();
+                match entry.header().entry_type() {
+                    tar::EntryType::Directory => self.dir(&name)?,
+                    tar::EntryType::Regular | tar::EntryType::Continuous => {
+                        let mode = entry.header().mode().ok();
+                        self.file(&name, entry, mode)?;
+                    }
+                    _ => {} // Links and special files are never created
+                }
+            }
+            Ok(())
+        }
+
+        fn count_entry(&mut self) -> anyhow::Result<()> {
+            self.entries += 1;
+            if self.entries > self.limits.max_entries {
+                anyhow::bail!("archive has more than {} entries", self.limits.max_entries);
+            }
+            Ok(())
+        }
+
+        /// Maps an entry name to a path below `dest`, refusing anything that could escape it.
+        fn resolve(&self, name: &str) -> anyhow::Result<PathBuf> {
+            let mut relative = PathBuf::new();
+            for component in Path::new(name).components() {
+                match component {
+                    Component::Normal(part) => relative.push(part),
+                    Component::CurDir => {}
+                    _ => anyhow::bail!("unsafe entry path {:?}", name),
+                }
+            }
+            Ok(self.dest.join(relative))
+        }
+
+        fn create_dirs(&mut self, dir: &Path) -> anyhow::Result<()> {
+            let missing: Vec<PathBuf> = dir
+                .ancestors()
+                .take_while(|d| d.symlink_metadata().is_err())
+                .map(Path::to_path_buf)
+                .collect();
+            for missing in missing.into_iter().rev() {
+                (self.on_create)(&missing)?;
+                std::fs::create_dir(&missing)?;
+                self.created.dirs.push(missing);
+            }
+            // A symlinked directory already in place must not lead outside
+            if !self.dest.as_os_str().is_empty() && !dir.canonicalize()?.starts_with(&self.dest) {
+                anyhow::bail!("{:?} leads outside of {:?}", dir, self.dest);
+            }
+            Ok(())
+        }
+
+        fn dir(&mut self, name: &str) -> anyhow::Result<()> {
+            let path = self.resolve(name)?;
+            self.create_dirs(&path)
+        }
+
+        fn file<R: Read>(&mut self, name: &str, reader: R, mode: Option<u32>) -> anyhow::Result<()> {
+            let mut path = self.resolve(name)?;
+            if path == self.dest {
+                return Ok(());
+            }
+            self.create_dirs(path.parent().unwrap())?;
+
+            if path.symlink_metadata().is_ok() {
+                match self.conflict {
+                    ConflictStrategy::Skip => return Ok(()),
+                    // Replacing would lose the existing file, which undo could not bring back
+                    ConflictStrategy::Overwrite => anyhow::bail!("{:?} already exists", path),
+                    ConflictStrategy::Rename => path = free_name(&path)?,
+                }
+            }
+
+            (self.on_create)(&path)?;
+            let mut out = OpenOptions::new().write(true).create_new(true).open(&path)?;
+            self.created.files.push(path.clone());
+
+            let limit = self.limits.max_bytes.min(self.max_ratio_bytes);
+            let remaining = limit.saturating_sub(self.written);
+            let copied = io::copy(&mut reader.take(remaining + 1), &mut out)?;
+            self.written += copied;
+            if self.written > self.limits.max_bytes {
+                anyhow::bail!("archive expands to more than {} bytes", self.limits.max_bytes);
+            }
+            if self.written > self.max_ratio_bytes {
+                anyhow::bail!("compression ratio exceeds {}:1", self.limits.max_ratio);
+            }
+
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                out.set_permissions(std::fs::Permissions::from_mode(mode & 0o777))?;
+            }
+            #[cfg(not(unix))]
+            let _ = mode;
+            Ok(())
+        }
+    }
+
+    /// First "stem (i).ext" next to `path` that does not exist yet.
+    fn free_name(path: &Path) -> anyhow::Result<PathBuf> {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = path.extension().map(|e| e.to_string_lossy());
+        for i in 1..1000 {
+            let name = match &ext {
+                Some(ext) => format!("{} ({}).{}", stem, i, ext),
+                None => format!("{} ({})", stem, i),
+            };
+            let candidate = path.with_file_name(name);
+            if candidate.symlink_metadata().is_err() {
+                return Ok(candidate);
+            }
+        }
+        anyhow::bail!("Too many file name conflicts for {:?}", path)
+    }
+
+    fn sha256<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+        let mut hasher = Sha256::new();
+        let mut buffer = [0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+}
+
+#[cfg(not(feature = "archive"))]
+mod imp {
+    use super::{ExtractLimits, MemberCache};
+    use crate::config::ConflictStrategy;
+    use std::path::{Path, PathBuf};
+
+    const DISABLED: &str = "Archive support is disabled (rebuild with --features archive)";
+
+    pub fn members(_archive: &Path) -> anyhow::Result<Vec<String>> {
+        anyhow::bail!(DISABLED)
+    }
+
+    pub fn append(_archive: &Path, _source: &Path, _cache: &MemberCache) -> anyhow::Result<String> {
+        anyhow::bail!(DISABLED)
+    }
+
+    pub fn extract_member(_archive: &Path, _member: &str, _dest: &Path) -> anyhow::Result<()> {
+        anyhow::bail!(DISABLED)
+    }
+
+    #[derive(Debug)]
+    pub struct Extracted {
+        pub files: Vec<PathBuf>,
+        pub dirs: Vec<PathBuf>,
+    }
+
+    impl Extracted {
+        pub fn remove(&self) {}
+    }
+
+    pub fn extract(
+        _archive: &Path,
+        _dest: &Path,
+        _conflict: &ConflictStrategy,
+        _limits: &ExtractLimits,
+        _on_create: &mut dyn FnMut(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Extracted> {
+        anyhow::bail!(DISABLED)
+    }
+}
+
+pub use imp::{append, extract, extract_member, members};
@@ -0,0 +1,159 @@
+use crate::dedup;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Per-user cache directory, `$XDG_CACHE_HOME/rarch` (usually `~/.cache/rarch`).
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("rarch"))
+}
+
+/// Modification time in nanoseconds since the epoch (0 if unavailable).
+pub fn mtime_ns(metadata: &std::fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos() as i64)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: i64,
+    hash: String,
+    /// Unix time of the last lookup, used by `prune`
+    used: i64,
+}
+
+/// On-disk cache of content hashes, keyed by device and inode and only
+/// trusted while the size and modification time of the file are unchanged.
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<(u64, u64), CachedHash>>,
+    dirty: AtomicBool,
+}
+
+impl HashCache {
+    /// The cache file in the cache directory `dir`.
+    pub fn path_in(dir: &Path) -> PathBuf {
+        dir.join("hashes.json")
+    }
+
+    /// Loads the cache, starting over if it is missing or unreadable.
+    pub fn load(path: PathBuf) -> Self {
+        let entries: Vec<CachedHash> = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            entries: Mutex::new(entries.into_iter().map(|e| ((e.dev, e.ino), e)).collect()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Hex SHA-256 of the file, from the cache when the file is unchanged.
+    pub fn hash(&self, path: &Path) -> std::io::Result<String> {
+        let metadata = std::fs::metadata(path)?;
+        let Some(key) = file_key(&metadata) else {
+            return dedup::hash_file(path);
+        };
+        let now = Utc::now().timestamp();
+        let mtime_ns = mtime_ns(&metadata);
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.get_mut(&key) {
+                if entry.size == metadata.len() && entry.mtime_ns == mtime_ns {
+                    // Refreshing the timestamp daily is precise enough for pruning
+                    if now - entry.used > 24 * 60 * 60 {
+                        entry.used = now;
+                        self.dirty.store(true, Ordering::Relaxed);
+                    }
+                    return Ok(entry.hash.clone());
+                }
+            }
+        }
+
+        let hash = dedup::hash_file(path)?;
+        let entry = CachedHash {
+            dev: key.0,
+            ino: key.1,
+            size: metadata.len(),
+            mtime_ns,
+            hash: hash.clone(),
+            used: now,
+        };
+        self.entries.lock().unwrap().insert(key, entry);
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(hash)
+    }
+
+    /// Writes the cache back if anything changed.
+    pub fn save(&self) -> anyhow::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let entries: Vec<CachedHash> = self.entries.lock().unwrap().values().cloned().collect();
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&entries)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Drops entries not used within `max_age`, then the least recently used
+    /// ones beyond `max_entries`. Returns how many were removed, or an error
+    /// if `max_age` reaches past the representable range of dates.
+    pub fn prune(&self, max_age: Duration, max_entries: Option<usize>) -> anyhow::Result<usize> {
+        let cutoff = Utc::now()
+            .checked_sub_signed(max_age)
+            .ok_or_else(|| anyhow::anyhow!("age out of range"))?
+            .timestamp();
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, e| e.used >= cutoff);
+        if let Some(max) = max_entries {
+            if entries.len() > max {
+                let mut by_use: Vec<((u64, u64), i64)> = entries.iter().map(|(k, e)| (*k, e.used)).collect();
+                by_use.sort_by_key(|(_, used)| std::cmp::Reverse(*used));
+                for (key, _) in &by_use[max..] {
+                    entries.remove(key);
+                }
+            }
+        }
+        let removed = before - entries.len();
+        if removed > 0 {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(unix)]
+fn file_key(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_key(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None // No stable file identity; hashes are not cached
+}
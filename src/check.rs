@@ -0,0 +1,539 @@
+use crate::archive::Format;
+use crate::config::{unknown_keys, Action, Condition, Config, ConflictStrategy, Rule};
+use crate::engine::{Engine, TYPE_PRESETS};
+use chrono::Utc;
+use globset::GlobBuilder;
+use ignore::gitignore::GitignoreBuilder;
+use regex::Regex;
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use toml::Spanned;
+
+/// Placeholders that `Engine::resolve_placeholders` always knows about.
+const KNOWN_PLACEHOLDERS: &[&str] = &["name", "filename", "ext", "year", "month", "day", "ai_name"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based line in the configuration file, if the problem can be located
+    pub line: Option<usize>,
+    pub rule: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "{}: ", line)?;
+        }
+        match self.severity {
+            Severity::Error => write!(f, "error: ")?,
+            Severity::Warning => write!(f, "warning: ")?,
+        }
+        if let Some(rule) = &self.rule {
+            write!(f, "rule '{}': ", rule)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Mirror of the `[[rules]]` array that only records where each value sits in the source.
+#[derive(serde::Deserialize)]
+struct SpanDocument {
+    #[serde(default)]
+    rules: Vec<NodeSpans>,
+}
+
+/// Where a value sits in the source, along with everything nested in it, so
+/// that conditions inside `all`/`any`/`not` can be reported at their own line.
+type NodeSpans = Spanned<SpanNode>;
+
+enum SpanNode {
+    Table(BTreeMap<String, NodeSpans>),
+    Array(Vec<NodeSpans>),
+    Scalar,
+}
+
+impl SpanNode {
+    fn get(&self, key: &str) -> Option<&NodeSpans> {
+        match self {
+            SpanNode::Table(fields) => fields.get(key),
+            _ => None,
+        }
+    }
+
+    /// The conditions under `all`, `any` or `not`, in the order `Condition` holds them.
+    fn conditions(&self, key: &str) -> Vec<&NodeSpans> {
+        match self.get(key) {
+            Some(node) => match node.get_ref() {
+                SpanNode::Array(items) => items.iter().collect(),
+                _ => vec![node],
+            },
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SpanNode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = SpanNode;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a TOML value")
+            }
+
+            fn visit_bool<E>(self, _: bool) -> Result<SpanNode, E> {
+                Ok(SpanNode::Scalar)
+            }
+
+            fn visit_i64<E>(self, _: i64) -> Result<SpanNode, E> {
+                Ok(SpanNode::Scalar)
+            }
+
+            fn visit_u64<E>(self, _: u64) -> Result<SpanNode, E> {
+                Ok(SpanNode::Scalar)
+            }
+
+            fn visit_f64<E>(self, _: f64) -> Result<SpanNode, E> {
+                Ok(SpanNode::Scalar)
+            }
+
+            fn visit_str<E>(self, _: &str) -> Result<SpanNode, E> {
+                Ok(SpanNode::Scalar)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SpanNode, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(SpanNode::Array(items))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SpanNode, A::Error> {
+                let mut fields = BTreeMap::new();
+                while let Some((key, value)) = map.next_entry::<String, NodeSpans>()? {
+                    fields.insert(key, value);
+                }
+                Ok(SpanNode::Table(fields))
+            }
+        }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+/// Validates a configuration file and returns every problem found, ordered by line.
+pub fn check_source(source: &str) -> Vec<Diagnostic> {
+    let config: Config = match toml::from_str(source) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                line: e.span().map(|span| line_of(source, span.start)),
+                rule: None,
+                message: e.message().to_string(),
+            }];
+        }
+    };
+    let spans: SpanDocument = match toml::from_str(source) {
+        Ok(spans) => spans,
+        Err(_) => SpanDocument { rules: Vec::new() },
+    };
+
+    let mut checker = Checker {
+        source,
+        diagnostics: Vec::new(),
+    };
+
+    // Loading a configuration only warns about these; checking it is strict
+    if let Ok(document) = source.parse::<toml::Table>() {
+        for unknown in unknown_keys(&document) {
+            let (line, rule) = match unknown.rule {
+                Some(index) => {
+                    let key = unknown.path.split(['.', '[']).next().unwrap_or_default();
+                    (checker.rule_line(spans.rules.get(index), key), config.rules.get(index))
+                }
+                None => (find_line(source, &unknown.path), None),
+            };
+            checker.push(Severity::Error, line, rule, format!("unknown field `{}`", unknown.path));
+        }
+    }
+
+    for pattern in &config.exclude {
+        if let Err(e) = GitignoreBuilder::new("/").add_line(None, pattern) {
+            let line = find_line(source, pattern);
+            checker.push(Severity::Error, line, None, format!("invalid exclude pattern {:?}: {}", pattern, e));
+        }
+    }
+
+    if let Some(threshold) = config.similarity_threshold.filter(|t| *t > 64) {
+        let line = find_line(source, "similarity_threshold");
+        checker.push(Severity::Error, line, None, format!("similarity_threshold {} is out of range (0-64)", threshold));
+    }
+
+    for (index, rule) in config.rules.iter().enumerate() {
+        let fields = spans.rules.get(index);
+        checker.check_rule(rule, fields);
+
+        if let Some(earlier) = config.rules[..index].iter().find(|earlier| shadows(earlier, rule)) {
+            let line = checker.rule_line(fields, "name");
+            checker.push(
+                Severity::Warning,
+                line,
+                Some(rule),
+                format!("is fully shadowed by earlier rule '{}' and will never match", earlier.name),
+            );
+        }
+    }
+
+    checker.diagnostics.sort_by_key(|d| d.line.unwrap_or(0));
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    source: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn push(&mut self, severity: Severity, line: Option<usize>, rule: Option<&Rule>, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            rule: rule.map(|r| r.name.clone()),
+            message,
+        });
+    }
+
+    /// Line of a field inside a rule or condition, falling back to where that starts.
+    fn rule_line(&self, fields: Option<&NodeSpans>, key: &str) -> Option<usize> {
+        let fields = fields?;
+        let offset = fields
+            .get_ref()
+            .get(key)
+            .map(|value| value.span().start)
+            .unwrap_or(fields.span().start);
+        Some(line_of(self.source, offset))
+    }
+
+    fn check_rule(&mut self, rule: &Rule, fields: Option<&NodeSpans>) {
+        if rule.name.trim().is_empty() {
+            let line = self.rule_line(fields, "name");
+            self.push(Severity::Warning, line, None, "rule has no name".to_string());
+        }
+
+        self.check_target(rule, fields);
+        if rule.action != Some(Action::Extract) {
+            for key in ["delete_source", "max_extract_size"] {
+                if fields.is_some_and(|f| f.get_ref().get(key).is_some()) {
+                    // Asking for a deletion that never happens is a mistake, not a no-op
+                    let severity = match key == "delete_source" && rule.delete_source {
+                        true => Severity::Error,
+                        false => Severity::Warning,
+                    };
+                    let line = self.rule_line(fields, key);
+                    self.push(severity, line, Some(rule), format!("{} only applies to the extract action", key));
+                }
+            }
+        }
+        if rule.action == Some(Action::Extract) && matches!(rule.conflict, Some(ConflictStrategy::Overwrite)) {
+            let line = self.rule_line(fields, "conflict");
+            self.push(Severity::Error, line, Some(rule), "conflict = \"overwrite\" is not supported by the extract action".to_string());
+        }
+        if rule.similar_to_existing.is_some() && !cfg!(feature = "phash") {
+            let line = self.rule_line(fields, "similar_to_existing");
+            self.push(Severity::Error, line, Some(rule), "similar_to_existing needs rarch built with the `phash` feature".to_string());
+        }
+
+        for (key, regex) in [("regex", &rule.regex), ("exclude_regex", &rule.exclude_regex)] {
+            if let Some(regex) = regex {
+                if let Err(e) = Regex::new(regex) {
+                    let line = self.rule_line(fields, key);
+                    self.push(Severity::Error, line, Some(rule), format!("invalid {} {:?}: {}", key, regex, e));
+                }
+            }
+        }
+        if let Some(glob) = &rule.not_path {
+            if let Err(e) = GlobBuilder::new(glob).build() {
+                let line = self.rule_line(fields, "not_path");
+                self.push(Severity::Error, line, Some(rule), format!("invalid not_path glob {:?}: {}", glob, e));
+            }
+        }
+        for (key, mime) in [("mime", &rule.mime), ("exclude_mime", &rule.exclude_mime)] {
+            if let Some(mime) = mime {
+                if !is_valid_mime_pattern(mime) {
+                    let line = self.rule_line(fields, key);
+                    self.push(Severity::Error, line, Some(rule), format!("invalid {} pattern {:?} (expected \"type/subtype\" or \"type/*\")", key, mime));
+                }
+            }
+        }
+        if let Some(rule_type) = &rule.r#type {
+            if !TYPE_PRESETS.contains(&rule_type.as_str()) {
+                let line = self.rule_line(fields, "type");
+                self.push(
+                    Severity::Error,
+                    line,
+                    Some(rule),
+                    format!("unknown type {:?} (expected one of: {})", rule_type, TYPE_PRESETS.join(", ")),
+                );
+            }
+        }
+        for (key, age) in [("older_than", &rule.older_than), ("newer_than", &rule.newer_than)] {
+            if let Some(age) = age {
+                if Engine::age_cutoff(age, Utc::now()).is_none() {
+                    // `older_than` may have been written under its old name
+                    let present = fields.is_some_and(|f| f.get_ref().get(key).is_some());
+                    let line = self.rule_line(fields, if present { key } else { "max_age" });
+                    self.push(Severity::Error, line, Some(rule), format!("cannot parse {} {:?}", key, age));
+                }
+            }
+        }
+        if rule.ai_prompt.is_some() && !cfg!(feature = "ai") {
+            let line = self.rule_line(fields, "ai_prompt");
+            self.push(Severity::Warning, line, Some(rule), "ai_prompt is ignored because rarch was built without the `ai` feature".to_string());
+        }
+
+        self.check_nested(rule, (&rule.all, &rule.any, &rule.not), fields);
+
+        if let Some(reason) = never_matches(rule) {
+            let line = self.rule_line(fields, "name");
+            self.push(Severity::Error, line, Some(rule), format!("can never match: {}", reason));
+        }
+    }
+
+    fn check_target(&mut self, rule: &Rule, fields: Option<&NodeSpans>) {
+        let line = self.rule_line(fields, "target");
+        if rule.action == Some(Action::Trash) {
+            if !rule.target.trim().is_empty() {
+                self.push(Severity::Warning, line, Some(rule), "target is ignored by the trash action".to_string());
+            }
+            return;
+        }
+        if rule.target.trim().is_empty() {
+            self.push(Severity::Error, line, Some(rule), "target is empty".to_string());
+            return;
+        }
+        if matches!(rule.action, Some(Action::Archive | Action::Extract)) && !cfg!(feature = "archive") {
+            self.push(Severity::Error, line, Some(rule), "archive actions need rarch built with the `archive` feature".to_string());
+        }
+        if rule.action == Some(Action::Archive) && Format::from_path(Path::new(&rule.target)).is_none() {
+            self.push(Severity::Error, line, Some(rule), format!("archive target {:?} must end in .zip, .tar, .tar.gz or .tar.zst", rule.target));
+        }
+
+        let mut rest = rule.target.as_str();
+        while let Some(start) = rest.find("${") {
+            let after = &rest[start + 2..];
+            let Some(end) = after.find('}') else {
+                self.push(Severity::Error, line, Some(rule), format!("unterminated placeholder in target {:?}", rule.target));
+                return;
+            };
+            let placeholder = &after[..end];
+            let is_extraction = rule.ai_extract.as_ref().is_some_and(|keys| keys.contains_key(placeholder));
+            if !KNOWN_PLACEHOLDERS.contains(&placeholder) && !is_extraction {
+                self.push(Severity::Error, line, Some(rule), format!("unknown placeholder ${{{}}} in target", placeholder));
+            }
+            rest = &after[end + 1..];
+        }
+    }
+
+    /// Checks the conditions under `all`, `any` and `not` of a rule or condition.
+    fn check_nested(&mut self, rule: &Rule, (all, any, not): Tree<'_>, fields: Option<&NodeSpans>) {
+        for key in ["all", "any", "not"] {
+            let conditions: Vec<&Condition> = match key {
+                "all" => all.iter().flatten().collect(),
+                "any" => any.iter().flatten().collect(),
+                _ => not.as_deref().into_iter().collect(),
+            };
+            let spans = fields.map(|f| f.get_ref().conditions(key)).unwrap_or_default();
+            // Without a span of its own, a condition is reported at its `all =`/`any =`/`not =` key
+            let line = self.rule_line(fields, key);
+            for (index, condition) in conditions.into_iter().enumerate() {
+                self.check_condition(rule, condition, spans.get(index).copied(), line);
+            }
+        }
+    }
+
+    fn check_condition(&mut self, rule: &Rule, condition: &Condition, fields: Option<&NodeSpans>, fallback: Option<usize>) {
+        if let Some(regex) = &condition.regex {
+            if let Err(e) = Regex::new(regex) {
+                let line = self.rule_line(fields, "regex").or(fallback);
+                self.push(Severity::Error, line, Some(rule), format!("invalid regex {:?} in condition: {}", regex, e));
+            }
+        }
+        if let Some(glob) = &condition.path {
+            if let Err(e) = GlobBuilder::new(glob).build() {
+                let line = self.rule_line(fields, "path").or(fallback);
+                self.push(Severity::Error, line, Some(rule), format!("invalid path glob {:?} in condition: {}", glob, e));
+            }
+        }
+        if let Some(mime) = &condition.mime {
+            if !is_valid_mime_pattern(mime) {
+                let line = self.rule_line(fields, "mime").or(fallback);
+                self.push(Severity::Error, line, Some(rule), format!("invalid mime pattern {:?} in condition", mime));
+            }
+        }
+        if let Some(rule_type) = &condition.r#type {
+            if !TYPE_PRESETS.contains(&rule_type.as_str()) {
+                let line = self.rule_line(fields, "type").or(fallback);
+                self.push(Severity::Error, line, Some(rule), format!("unknown type {:?} in condition", rule_type));
+            }
+        }
+        for (key, age) in [("older_than", &condition.older_than), ("newer_than", &condition.newer_than)] {
+            if let Some(age) = age {
+                if Engine::age_cutoff(age, Utc::now()).is_none() {
+                    // `older_than` may have been written under its old name
+                    let present = fields.is_some_and(|f| f.get_ref().get(key).is_some());
+                    let line = self.rule_line(fields, if present { key } else { "max_age" }).or(fallback);
+                    self.push(Severity::Error, line, Some(rule), format!("cannot parse age {:?} in condition", age));
+                }
+            }
+        }
+        if let (Some(min), Some(max)) = (condition.min_size, condition.max_size) {
+            if min > max {
+                let line = self.rule_line(fields, "min_size").or(fallback);
+                self.push(Severity::Error, line, Some(rule), format!("condition can never match: min_size {} is larger than max_size {}", min, max));
+            }
+        }
+        self.check_nested(rule, (&condition.all, &condition.any, &condition.not), fields);
+    }
+}
+
+/// The `all`, `any` and `not` fields shared by rules and conditions.
+type Tree<'a> = (&'a Option<Vec<Condition>>, &'a Option<Vec<Condition>>, &'a Option<Box<Condition>>);
+
+/// Returns why a rule cannot match any file, if that can be decided statically.
+fn never_matches(rule: &Rule) -> Option<String> {
+    let has_tree = rule.all.is_some() || rule.any.is_some() || rule.not.is_some();
+    let has_flat = rule.mime.is_some()
+        || rule.r#type.is_some()
+        || rule.extensions.is_some()
+        || rule.regex.is_some()
+        || rule.ai_prompt.is_some();
+    if !has_flat && !has_tree {
+        return Some("it has no matchers (extensions, regex, mime, type, ai_prompt or all/any/not)".to_string());
+    }
+    if has_flat && rule.mime.is_none() && rule.r#type.is_none() && rule.regex.is_none() && rule.ai_prompt.is_none() {
+        let exts = rule.extensions.as_deref().unwrap_or_default();
+        if exts.is_empty() {
+            return Some("its extensions list is empty".to_string());
+        }
+        if let Some(excluded) = &rule.exclude_extensions {
+            let excluded: Vec<String> = excluded.iter().map(|e| e.to_lowercase()).collect();
+            if exts.iter().all(|e| excluded.contains(&e.to_lowercase())) {
+                return Some("every extension is also listed in exclude_extensions".to_string());
+            }
+        }
+    }
+    if let (Some(min), Some(max)) = (rule.min_size, rule.max_size) {
+        if min > max {
+            return Some(format!("min_size {} is larger than max_size {}", min, max));
+        }
+    }
+    if let (Some(older), Some(newer)) = (&rule.older_than, &rule.newer_than) {
+        let now = Utc::now();
+        if let (Some(older_cutoff), Some(newer_cutoff)) = (Engine::age_cutoff(older, now), Engine::age_cutoff(newer, now)) {
+            if older_cutoff <= newer_cutoff {
+                return Some(format!("older_than {:?} and newer_than {:?} do not overlap", older, newer));
+            }
+        }
+    }
+    if let Some(any) = &rule.any {
+        if any.is_empty() {
+            return Some("its `any` list is empty".to_string());
+        }
+    }
+    None
+}
+
+/// True if every file that `later` could match is already claimed by `earlier`.
+/// Only decided for rules whose outcome is certain: `earlier` must have no
+/// filters, exclusions or conditions, and AI matchers are never considered.
+fn shadows(earlier: &Rule, later: &Rule) -> bool {
+    let unrestricted = earlier.min_size.is_none()
+        && earlier.max_size.is_none()
+        && earlier.older_than.is_none()
+        && earlier.newer_than.is_none()
+        && earlier.exclude_extensions.is_none()
+        && earlier.exclude_regex.is_none()
+        && earlier.exclude_mime.is_none()
+        && earlier.not_path.is_none()
+        && earlier.similar_to_existing.is_none()
+        && earlier.all.is_none()
+        && earlier.any.is_none()
+        && earlier.not.is_none();
+    if !unrestricted || later.ai_prompt.is_some() {
+        return false;
+    }
+
+    let mut has_matcher = false;
+    if let Some(exts) = &later.extensions {
+        has_matcher = true;
+        let earlier_exts: Vec<String> = earlier.extensions.iter().flatten().map(|e| e.to_lowercase()).collect();
+        if exts.is_empty() || !exts.iter().all(|e| earlier_exts.contains(&e.to_lowercase())) {
+            return false;
+        }
+    }
+    if let Some(regex) = &later.regex {
+        has_matcher = true;
+        if earlier.regex.as_ref() != Some(regex) {
+            return false;
+        }
+    }
+    if let Some(mime) = &later.mime {
+        has_matcher = true;
+        if !mime_covered(mime, earlier) {
+            return false;
+        }
+    }
+    if let Some(rule_type) = &later.r#type {
+        has_matcher = true;
+        let by_type = earlier.r#type.as_ref() == Some(rule_type);
+        let by_mime = earlier.mime.as_deref() == Some(format!("{}/*", rule_type).as_str()) && rule_type != "document";
+        if !by_type && !by_mime {
+            return false;
+        }
+    }
+    has_matcher
+}
+
+fn mime_covered(mime: &str, earlier: &Rule) -> bool {
+    if let Some(earlier_mime) = &earlier.mime {
+        if earlier_mime == mime || (earlier_mime.ends_with("/*") && mime.starts_with(&earlier_mime[..earlier_mime.len() - 1])) {
+            return true;
+        }
+    }
+    match earlier.r#type.as_deref() {
+        Some(preset @ ("image" | "video" | "audio")) => mime.starts_with(&format!("{}/", preset)),
+        Some("document") => !mime.ends_with("/*") && (mime.contains("pdf") || mime.contains("word") || mime.contains("text")),
+        _ => false,
+    }
+}
+
+fn is_valid_mime_pattern(mime: &str) -> bool {
+    let Some((top, sub)) = mime.split_once('/') else {
+        return false;
+    };
+    let valid_token = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c));
+    valid_token(top) && (sub == "*" || valid_token(sub))
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+/// Best-effort location of a top-level value that has no span of its own.
+fn find_line(source: &str, needle: &str) -> Option<usize> {
+    source.find(needle).map(|offset| line_of(source, offset))
+}
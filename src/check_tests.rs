@@ -0,0 +1,170 @@
+#[cfg(test)]
+mod tests {
+    use crate::check::{check_source, Severity};
+
+    fn errors_on(source: &str) -> Vec<(Option<usize>, String)> {
+        check_source(source)
+            .into_iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| (d.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_accepts_valid_config() {
+        let source = r#"
+[[rules]]
+name = "Photos"
+type = "image"
+target = "Pictures/${year}/${month}"
+
+[[rules]]
+name = "Docs"
+extensions = ["pdf"]
+older_than = "1w3d"
+min_size = "1MB"
+target = "Documents/${name}.${ext}"
+"#;
+        assert!(check_source(source).is_empty(), "{:?}", check_source(source));
+    }
+
+    #[test]
+    fn test_check_reports_unknown_fields_with_line() {
+        let source = "[[rules]]\nname = \"Photos\"\nextension = [\"jpg\"]\ntarget = \"x\"\n";
+        let errors = errors_on(source);
+        // The misspelt matcher also leaves the rule without any
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        let unknown = errors.iter().find(|(_, m)| m.contains("`extension`")).unwrap();
+        assert_eq!(unknown.0, Some(3));
+    }
+
+    #[test]
+    fn test_unknown_fields_are_only_rejected_by_check() {
+        let source = r#"hash_cahce = false
+
+[[rules]]
+name = "Old"
+max_age = "30d"
+target = "Archive"
+
+[[rules.any]]
+extensions = ["log"]
+mine = "text/plain"
+"#;
+        let errors = errors_on(source);
+        let find = |needle: &str| errors.iter().find(|(_, m)| m.contains(needle)).map(|(l, _)| *l);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(find("`hash_cahce`"), Some(Some(1)));
+        assert_eq!(find("`any[0].mine`"), Some(Some(8)));
+
+        // Loading the same file only warns, so existing configurations keep working
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rarch.toml");
+        std::fs::write(&path, source).unwrap();
+        let config = crate::config::Config::from_file(path).unwrap();
+        assert_eq!(config.rules[0].older_than.as_deref(), Some("30d"));
+    }
+
+    #[test]
+    fn test_check_reports_semantic_errors() {
+        let source = r#"
+[[rules]]
+name = "Broken"
+regex = "([a-z"
+mime = "imagepng"
+type = "picture"
+target = "Sorted/${yaer}"
+
+[[rules]]
+name = "Empty"
+target = "Nowhere"
+
+[[rules]]
+name = "Impossible"
+extensions = ["log"]
+min_size = "10MB"
+max_size = "1MB"
+target = "Logs"
+
+[[rules]]
+name = "Unpack"
+extensions = ["zip"]
+action = "extract"
+conflict = "overwrite"
+target = "Unpacked"
+
+[[rules]]
+name = "Tidy"
+extensions = ["tmp"]
+delete_source = true
+target = "Tmp"
+"#;
+        let errors = errors_on(source);
+        let find = |needle: &str| errors.iter().find(|(_, m)| m.contains(needle)).map(|(l, _)| *l);
+        assert_eq!(find("invalid regex"), Some(Some(4)));
+        assert_eq!(find("invalid mime"), Some(Some(5)));
+        assert_eq!(find("unknown type"), Some(Some(6)));
+        assert_eq!(find("${yaer}"), Some(Some(7)));
+        assert_eq!(find("no matchers"), Some(Some(10)));
+        assert_eq!(find("larger than max_size"), Some(Some(14)));
+        assert_eq!(find("not supported by the extract action"), Some(Some(24)));
+        assert_eq!(find("delete_source only applies"), Some(Some(30)));
+    }
+
+    #[test]
+    fn test_check_reports_nested_conditions_at_their_own_line() {
+        let source = r#"
+[[rules]]
+name = "Nested"
+target = "Sorted"
+all = [
+    { extensions = ["log"] },
+    { any = [
+        { mime = "text/plain" },
+        { regex = "([a-z" },
+    ] },
+]
+not = { type = "picture" }
+
+[[rules.any]]
+extensions = ["txt"]
+
+[[rules.any]]
+older_than = "soon"
+"#;
+        let errors = errors_on(source);
+        let find = |needle: &str| errors.iter().find(|(_, m)| m.contains(needle)).map(|(l, _)| *l);
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert_eq!(find("invalid regex"), Some(Some(9)));
+        assert_eq!(find("unknown type"), Some(Some(12)));
+        assert_eq!(find("cannot parse age"), Some(Some(18)));
+    }
+
+    #[test]
+    fn test_check_reports_shadowed_rules() {
+        let source = r#"
+[[rules]]
+name = "Images"
+type = "image"
+target = "Images"
+
+[[rules]]
+name = "PNGs"
+mime = "image/png"
+target = "PNG"
+
+[[rules]]
+name = "Big PNGs"
+mime = "image/png"
+target = "Big"
+"#;
+        let warnings: Vec<_> = check_source(source)
+            .into_iter()
+            .filter(|d| d.severity == Severity::Warning)
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].rule.as_deref(), Some("PNGs"));
+        assert!(warnings[0].message.contains("'Images'"));
+        assert_eq!(warnings[0].line, Some(8));
+    }
+}
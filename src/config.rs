@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use anyhow::Context;
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer};
 use std::path::PathBuf;
 
 #[derive(Debug, Deserialize, Default)]
@@ -8,6 +10,64 @@ pub struct Config {
     pub ai_api_base: String,
     #[serde(default = "default_model")]
     pub ai_model: String,
+    /// How many directory levels below the base directory to scan (1 = top level only, 0 = unlimited)
+    pub max_depth: Option<usize>,
+    /// Also scan hidden files and directories (names starting with '.')
+    #[serde(default)]
+    pub include_hidden: bool,
+    /// Descend into symlinked directories
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Allow the scan to cross into other mounted filesystems
+    #[serde(default)]
+    pub cross_mounts: bool,
+    /// Gitignore-style patterns that are never organized, relative to the base directory
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// How duplicates found within one run are deduplicated
+    #[serde(default)]
+    pub dedup: DedupMode,
+    /// What to do with incoming files that already have an identical twin in a target directory
+    pub dedup_existing: Option<ExistingDedup>,
+    /// Location of the target index, relative to the config file (defaults to `$XDG_CACHE_HOME/rarch/target_index.json`)
+    pub dedup_index: Option<PathBuf>,
+    /// Largest perceptual hash distance (0-64) at which images count as similar
+    pub similarity_threshold: Option<u32>,
+    /// Remember content hashes in the cache directory between runs
+    #[serde(default = "default_true")]
+    pub hash_cache: bool,
+    /// Where the hash cache and target index live, relative to the config file (defaults to `$XDG_CACHE_HOME/rarch`)
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// How the extra copies in a set of identical files are dealt with. The
+/// first file of a set is always organized normally.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    /// Replace duplicates with hard links to the original
+    #[default]
+    Hardlink,
+    /// Replace duplicates with copy-on-write clones of the original (btrfs, xfs)
+    Reflink,
+    /// Replace duplicates with symbolic links to the original
+    Symlink,
+    /// Remove duplicates; `rarch undo` copies the original back
+    Delete,
+    /// Only plan: list the operations and duplicate sets without touching any file
+    Report,
+    /// Do not look for duplicates
+    Off,
+}
+
+/// Handling of files whose content already exists in a target directory.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingDedup {
+    /// Replace the incoming file with a hard link to its twin
+    Hardlink,
+    /// Leave the incoming file alone and report it
+    Skip,
 }
 
 fn default_api_base() -> String {
@@ -18,6 +78,10 @@ fn default_model() -> String {
     "qwen2:0.5b".to_string()
 }
 
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Deserialize, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ConflictStrategy {
@@ -27,6 +91,33 @@ pub enum ConflictStrategy {
     Skip,
 }
 
+/// What a rule does with a matching file.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Move,
+    Copy,
+    Symlink,
+    Hardlink,
+    /// Send the file to the freedesktop.org trash; `target` is not used
+    Trash,
+    /// Add the file to the archive named by `target` (`.zip`, `.tar`, `.tar.gz` or `.tar.zst`)
+    Archive,
+    /// Unpack the archive into the directory named by `target`
+    Extract,
+}
+
+/// The file timestamp used for age filters.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AgeBasis {
+    #[default]
+    Modified,
+    Created,
+    Accessed,
+}
+
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct Rule {
     #[allow(dead_code)]
@@ -34,20 +125,232 @@ pub struct Rule {
     pub extensions: Option<Vec<String>>,
     pub regex: Option<String>,
     pub ai_prompt: Option<String>,
+    #[cfg_attr(not(feature = "ai"), allow(dead_code))]
     pub ai_rename_prompt: Option<String>,
     pub ai_extract: Option<std::collections::HashMap<String, String>>,
+    #[serde(default)]
     pub target: String,
+    /// Minimum file size, in bytes or with a unit ("10MB", "2GiB")
+    #[serde(default, deserialize_with = "deserialize_size")]
     pub min_size: Option<u64>,
-    pub max_age: Option<String>,
+    /// Maximum file size, in bytes or with a unit ("10MB", "2GiB")
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_size: Option<u64>,
+    /// Only match files at least this old ("30d", "1w3d") or from before a date ("2024-01-31")
+    #[serde(alias = "max_age")]
+    pub older_than: Option<String>,
+    /// Only match files younger than this age or from after a date
+    pub newer_than: Option<String>,
+    /// Which timestamp `older_than`/`newer_than` are compared against
+    pub age_basis: Option<AgeBasis>,
     pub mime: Option<String>,
     pub r#type: Option<String>,
     pub conflict: Option<ConflictStrategy>,
+    /// What to do with a matching file (defaults to moving it)
+    pub action: Option<Action>,
+    /// Send the archive to the trash once it has been extracted
+    #[serde(default)]
+    pub delete_source: bool,
+    /// Refuse to extract archives that unpack to more than this (default 4GiB)
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_extract_size: Option<u64>,
+    /// Only match images that look like (`true`) or unlike (`false`) a picture
+    /// already in a target directory, by perceptual hash
+    pub similar_to_existing: Option<bool>,
+    /// Extensions (by content or filename) that are never matched by this rule
+    pub exclude_extensions: Option<Vec<String>>,
+    /// Filenames matching this pattern are never matched by this rule
+    pub exclude_regex: Option<String>,
+    /// MIME type (or `type/*` wildcard) that is never matched by this rule
+    pub exclude_mime: Option<String>,
+    /// Glob on the path relative to the base directory that is never matched by this rule
+    pub not_path: Option<String>,
+    /// Every condition must hold
+    pub all: Option<Vec<Condition>>,
+    /// At least one condition must hold
+    pub any: Option<Vec<Condition>>,
+    /// The condition must not hold
+    pub not: Option<Box<Condition>>,
+}
+
+/// A node of a nested match expression. All matchers set on the same node must
+/// hold; `all`, `any` and `not` combine child nodes into arbitrary trees.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Condition {
+    pub extensions: Option<Vec<String>>,
+    pub mime: Option<String>,
+    pub r#type: Option<String>,
+    pub regex: Option<String>,
+    /// Glob on the path relative to the base directory
+    pub path: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub min_size: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_size: Option<u64>,
+    #[serde(alias = "max_age")]
+    pub older_than: Option<String>,
+    pub newer_than: Option<String>,
+    pub age_basis: Option<AgeBasis>,
+    pub ai_prompt: Option<String>,
+    pub all: Option<Vec<Condition>>,
+    pub any: Option<Vec<Condition>>,
+    pub not: Option<Box<Condition>>,
 }
 
 impl Config {
     pub fn from_file(path: PathBuf) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config {:?}", path))?;
+        let mut config: Config = toml::from_str(&content)
+            .with_context(|| format!("Invalid config {:?} (run 'rarch check' for details)", path))?;
+        // Unknown settings are only rejected by 'rarch check', so older files keep working
+        if let Ok(document) = content.parse::<toml::Table>() {
+            for unknown in unknown_keys(&document) {
+                let key = match unknown.rule {
+                    Some(index) => format!("rules[{}].{}", index, unknown.path),
+                    None => unknown.path,
+                };
+                eprintln!("Warning: ignoring unknown setting `{}` in {:?} (run 'rarch check' for details)", key, path);
+            }
+        }
+        // Relative paths in the file are relative to the file itself
+        if let Some(dir) = path.parent() {
+            for setting in [&mut config.dedup_index, &mut config.cache_dir] {
+                if let Some(relative) = setting.as_mut() {
+                    *relative = dir.join(&*relative);
+                }
+            }
+        }
         Ok(config)
     }
 }
+
+/// A key in the configuration file that no setting reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownKey {
+    /// Index of the rule the key is in, if any
+    pub rule: Option<usize>,
+    /// Dotted path of the key, relative to its rule (`extension`, `all[1].mine`)
+    pub path: String,
+}
+
+/// Lists the keys of a parsed configuration file that `Config`, `Rule` and
+/// `Condition` do not know about. Deserialization ignores them, so a typo
+/// would otherwise silently disable a setting.
+pub fn unknown_keys(document: &toml::Table) -> Vec<UnknownKey> {
+    let mut unknown = Vec::new();
+    for key in document.keys().filter(|key| !field_names::<Config>().contains(&key.as_str())) {
+        unknown.push(UnknownKey { rule: None, path: key.clone() });
+    }
+    let rules = document.get("rules").and_then(toml::Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    for (index, rule) in rules.iter().enumerate() {
+        if let Some(table) = rule.as_table() {
+            let mut paths = Vec::new();
+            unknown_in(table, field_names::<Rule>(), "", &mut paths);
+            unknown.extend(paths.into_iter().map(|path| UnknownKey { rule: Some(index), path }));
+        }
+    }
+    unknown
+}
+
+/// Collects the unknown keys of `table` and of the conditions nested in it.
+fn unknown_in(table: &toml::Table, known: &[&str], prefix: &str, paths: &mut Vec<String>) {
+    for (key, value) in table {
+        let path = format!("{}{}", prefix, key);
+        if !known.contains(&key.as_str()) {
+            paths.push(path);
+            continue;
+        }
+        match (key.as_str(), value) {
+            ("all" | "any", toml::Value::Array(conditions)) => {
+                for (index, condition) in conditions.iter().enumerate() {
+                    if let Some(condition) = condition.as_table() {
+                        unknown_in(condition, field_names::<Condition>(), &format!("{}[{}].", path, index), paths);
+                    }
+                }
+            }
+            ("not", toml::Value::Table(condition)) => {
+                unknown_in(condition, field_names::<Condition>(), &format!("{}.", path), paths);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The keys (aliases included) a derived `Deserialize` struct accepts, as
+/// serde hands them to `deserialize_struct`.
+fn field_names<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldNames<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Parses a size such as `1024`, `10MB`, `1.5GiB` or `300k` into bytes.
+/// SI units (`KB`, `MB`, ...) are powers of 1000, IEC units (`KiB`, `MiB`, ...)
+/// and bare letters (`K`, `M`, ...) are powers of 1024.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num_part, unit_part) = s.split_at(split);
+    let num = num_part.parse::<f64>().ok()?;
+    let multiplier: u64 = match unit_part.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000u64.pow(2),
+        "gb" => 1000u64.pow(3),
+        "tb" => 1000u64.pow(4),
+        "k" | "kib" => 1024,
+        "m" | "mib" => 1024u64.pow(2),
+        "g" | "gib" => 1024u64.pow(3),
+        "t" | "tib" => 1024u64.pow(4),
+        _ => return None,
+    };
+    Some((num * multiplier as f64).round() as u64)
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SizeValue {
+        Bytes(u64),
+        Human(String),
+    }
+
+    match Option::<SizeValue>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(SizeValue::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(SizeValue::Human(text)) => parse_size(&text)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid size {:?}", text))),
+    }
+}
@@ -0,0 +1,137 @@
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Bytes hashed at each end of a file in the second stage.
+const EDGE_BLOCK: u64 = 64 * 1024;
+/// Read buffer for full-content hashes.
+const HASH_BUFFER: usize = 1024 * 1024;
+
+/// Finds sets of files with identical content.
+///
+/// The work is staged so that most files are never read in full: files are
+/// grouped by size, each group is split by a hash of its first and last
+/// block, and only what is left is hashed completely. Empty files are
+/// ignored. Every returned group holds at least two paths, sorted, so the
+/// first one can be kept as the original.
+/// Full hashes come from `hash`, which may be backed by a cache.
+pub fn find_duplicates<H>(files: &[PathBuf], hash: H) -> Vec<Vec<PathBuf>>
+where
+    H: Fn(&Path) -> std::io::Result<String> + Sync,
+{
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in files {
+        if let Ok(metadata) = std::fs::metadata(path) {
+            if metadata.len() > 0 {
+                by_size.entry(metadata.len()).or_default().push(path.clone());
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<PathBuf>> = by_size
+        .into_par_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .flat_map(|(size, group)| {
+            let mut groups = Vec::new();
+            for group in split_by(group, |path| edge_hash(path, size)) {
+                if size <= 2 * EDGE_BLOCK {
+                    // The edges already covered the whole file
+                    groups.push(group);
+                } else {
+                    groups.extend(split_by(group, &hash));
+                }
+            }
+            groups
+        })
+        .collect();
+
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort();
+    groups
+}
+
+/// Splits a group by `key`, dropping unreadable files and groups of one.
+fn split_by<K, F>(group: Vec<PathBuf>, key: F) -> Vec<Vec<PathBuf>>
+where
+    K: Eq + Hash + Send,
+    F: Fn(&Path) -> std::io::Result<K> + Sync,
+{
+    let keyed: Vec<(K, PathBuf)> = group
+        .into_par_iter()
+        .filter_map(|path| key(&path).ok().map(|k| (k, path)))
+        .collect();
+    let mut split: HashMap<K, Vec<PathBuf>> = HashMap::new();
+    for (k, path) in keyed {
+        split.entry(k).or_default().push(path);
+    }
+    split.into_values().filter(|group| group.len() > 1).collect()
+}
+
+/// SHA-256 of the first and last `EDGE_BLOCK` bytes.
+fn edge_hash(path: &Path, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; EDGE_BLOCK.min(size) as usize];
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+    if size > EDGE_BLOCK {
+        let tail = EDGE_BLOCK.min(size - EDGE_BLOCK);
+        file.seek(SeekFrom::Start(size - tail))?;
+        buffer.truncate(tail as usize);
+        file.read_exact(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// True if both files hold exactly the same bytes.
+pub fn same_content(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut buffer_a = vec![0; HASH_BUFFER];
+    let mut buffer_b = vec![0; HASH_BUFFER];
+    loop {
+        let n = read_full(&mut a, &mut buffer_a)?;
+        if n != read_full(&mut b, &mut buffer_b)? || buffer_a[..n] != buffer_b[..n] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fills `buffer` as far as the file allows; returns the number of bytes read.
+fn read_full(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Hex SHA-256 of the whole file.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_BUFFER];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,292 @@
+use crate::cache::mtime_ns;
+use crate::engine::Engine;
+use crate::journal::{OpType, Operation, Session};
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Which copy of a duplicate set `rarch dupes` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Keep {
+    /// The most recently modified copy
+    Newest,
+    /// The least recently modified copy
+    Oldest,
+    /// The copy with the shortest path
+    Shortest,
+}
+
+/// What happens to the copies that are not kept.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DupeAction {
+    /// Replace them with hard links to the kept copy
+    Hardlink,
+    /// Move them to the trash
+    Trash,
+}
+
+pub struct DupesOptions {
+    pub keep: Keep,
+    /// Copies inside this directory are kept first (canonical path)
+    pub prefer: Option<PathBuf>,
+    pub json: bool,
+    pub interactive: bool,
+    pub action: Option<DupeAction>,
+    pub yes: bool,
+    /// Where applied changes are journaled
+    pub session: Session,
+}
+
+/// A duplicate set with the copy to keep moved to the front.
+#[derive(Debug, Serialize)]
+pub struct DuplicateSet {
+    pub size: u64,
+    pub keep: PathBuf,
+    pub duplicates: Vec<PathBuf>,
+}
+
+impl DuplicateSet {
+    fn new(mut files: Vec<PathBuf>, keeper: usize) -> Self {
+        let keep = files.remove(keeper);
+        Self {
+            size: std::fs::metadata(&keep).map(|m| m.len()).unwrap_or(0),
+            keep,
+            duplicates: files,
+        }
+    }
+
+    fn reclaimable(&self) -> u64 {
+        self.size * self.duplicates.len() as u64
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    groups: &'a [DuplicateSet],
+    reclaimable: u64,
+}
+
+/// Index of the copy to keep. Copies inside `prefer` come first, then the
+/// `keep` strategy decides, then the path order.
+pub fn pick_keeper(files: &[PathBuf], keep: Keep, prefer: Option<&Path>) -> usize {
+    let rank = |path: &PathBuf| {
+        let preferred = prefer.is_some_and(|dir| path.canonicalize().is_ok_and(|p| p.starts_with(dir)));
+        let mtime = std::fs::metadata(path).map(|m| mtime_ns(&m) as i128).unwrap_or(0);
+        let score = match keep {
+            Keep::Newest => -mtime,
+            Keep::Oldest => mtime,
+            Keep::Shortest => path.as_os_str().len() as i128,
+        };
+        (!preferred, score)
+    };
+    (0..files.len()).min_by_key(|&i| rank(&files[i])).unwrap_or(0)
+}
+
+/// Lists the duplicates below the engine's base directory and, with an
+/// action, replaces or trashes every copy but the kept one.
+pub fn run_dupes(engine: &Engine, options: &DupesOptions) -> anyhow::Result<()> {
+    let groups = engine.scan_duplicates();
+    let mut sets: Vec<DuplicateSet> = groups
+        .into_iter()
+        .map(|files| {
+            let keeper = pick_keeper(&files, options.keep, options.prefer.as_deref());
+            DuplicateSet::new(files, keeper)
+        })
+        .collect();
+    let reclaimable: u64 = sets.iter().map(DuplicateSet::reclaimable).sum();
+
+    if options.json {
+        let report = Report { groups: &sets, reclaimable };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if sets.is_empty() {
+        println!("No duplicates found.");
+        return Ok(());
+    }
+
+    if options.interactive {
+        sets = choose_interactively(sets)?;
+    } else {
+        for (index, set) in sets.iter().enumerate() {
+            print_set(index, sets.len(), set);
+        }
+        println!(
+            "\n{} duplicate groups, {} redundant files, {:.2} MB reclaimable.",
+            sets.len(),
+            sets.iter().map(|s| s.duplicates.len()).sum::<usize>(),
+            reclaimable as f64 / 1024.0 / 1024.0
+        );
+    }
+
+    let Some(action) = options.action else {
+        return Ok(());
+    };
+    let count: usize = sets.iter().map(|s| s.duplicates.len()).sum();
+    if count == 0 {
+        println!("Nothing to do.");
+        return Ok(());
+    }
+    if !options.yes && !options.interactive {
+        let verb = match action {
+            DupeAction::Hardlink => "replace",
+            DupeAction::Trash => "trash",
+        };
+        print!("About to {} {} files. Do you want to proceed? [y/N] ", verb, count);
+        std::io::stdout().flush()?;
+        if read_answer()?.as_deref() != Some("y") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    let mut journaled = 0;
+    let mut reclaimed = 0u64;
+    for set in &sets {
+        for duplicate in &set.duplicates {
+            let op = Operation {
+                from: duplicate.clone(),
+                to: set.keep.clone(),
+                op_type: match action {
+                    DupeAction::Hardlink => OpType::TwinLink,
+                    DupeAction::Trash => OpType::Trash(PathBuf::new()),
+                },
+                rule_name: None,
+            };
+            let session = Some(&options.session);
+            // Linking verifies on its own; trashing is checked here just the same
+            let result = match action {
+                DupeAction::Trash => engine
+                    .verify_duplicate(&op.from, &op.to)
+                    .and_then(|_| engine.apply_journaled(&op, &op.to, session)),
+                DupeAction::Hardlink => engine.apply_journaled(&op, &op.to, session),
+            };
+            match result {
+                Ok(done) => {
+                    reclaimed += set.size;
+                    journaled += done.len();
+                }
+                Err(e) => println!("Failed: {:?}: {:#}", duplicate, e),
+            }
+        }
+    }
+
+    if journaled == 0 {
+        println!("No actions were performed.");
+        return Ok(());
+    }
+    println!(
+        "\nDeduplicated {} files, {:.2} MB reclaimed. You can undo this with 'rarch undo'.",
+        journaled,
+        reclaimed as f64 / 1024.0 / 1024.0
+    );
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct SimilarFile {
+    path: PathBuf,
+    size: u64,
+}
+
+/// Lists groups of images that look alike (perceptual hashes within
+/// `threshold` bits). They are only reported, as they are not identical.
+pub fn run_similar(engine: &Engine, threshold: u32, json: bool) -> anyhow::Result<()> {
+    let groups: Vec<Vec<SimilarFile>> = engine
+        .scan_similar_images(threshold)
+        .into_iter()
+        .map(|group| {
+            group
+                .into_iter()
+                .map(|path| SimilarFile {
+                    size: std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                    path,
+                })
+                .collect()
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "similar": groups }))?);
+        return Ok(());
+    }
+    if groups.is_empty() {
+        println!("No similar images found.");
+        return Ok(());
+    }
+    for (index, group) in groups.iter().enumerate() {
+        println!("Group {}/{}: {} similar images", index + 1, groups.len(), group.len());
+        for file in group {
+            println!("  {:>12}  {}", file.size, file.path.display());
+        }
+    }
+    println!("\n{} groups of similar images.", groups.len());
+    Ok(())
+}
+
+fn print_set(index: usize, total: usize, set: &DuplicateSet) {
+    println!(
+        "Group {}/{}: {} copies of {} bytes ({:.2} MB reclaimable)",
+        index + 1,
+        total,
+        set.duplicates.len() + 1,
+        set.size,
+        set.reclaimable() as f64 / 1024.0 / 1024.0
+    );
+    println!("  keep  {}", set.keep.display());
+    for duplicate in &set.duplicates {
+        println!("        {}", duplicate.display());
+    }
+}
+
+/// Asks which copy to keep for each set. Skipped sets are left untouched.
+fn choose_interactively(sets: Vec<DuplicateSet>) -> anyhow::Result<Vec<DuplicateSet>> {
+    let total = sets.len();
+    let mut chosen = Vec::new();
+    for (index, set) in sets.into_iter().enumerate() {
+        println!("\nGroup {}/{}: {} copies of {} bytes", index + 1, total, set.duplicates.len() + 1, set.size);
+        let mut files = vec![set.keep];
+        files.extend(set.duplicates);
+        for (i, path) in files.iter().enumerate() {
+            let modified = std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .map(|t| DateTime::<Local>::from(t).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let marker = if i == 0 { " (suggested)" } else { "" };
+            println!("  [{}] {}  {}{}", i + 1, modified, path.display(), marker);
+        }
+
+        loop {
+            print!("Keep which copy? [1-{}, Enter = 1, s = skip, q = quit] ", files.len());
+            std::io::stdout().flush()?;
+            let Some(answer) = read_answer()? else {
+                return Ok(chosen);
+            };
+            match answer.as_str() {
+                "" => {
+                    chosen.push(DuplicateSet::new(files, 0));
+                    break;
+                }
+                "s" => break,
+                "q" => return Ok(chosen),
+                _ => match answer.parse::<usize>() {
+                    Ok(n) if (1..=files.len()).contains(&n) => {
+                        chosen.push(DuplicateSet::new(files, n - 1));
+                        break;
+                    }
+                    _ => println!("Please enter a number between 1 and {}.", files.len()),
+                },
+            }
+        }
+    }
+    Ok(chosen)
+}
+
+/// Reads one trimmed, lowercased line; `None` once stdin is closed.
+fn read_answer() -> anyhow::Result<Option<String>> {
+    let mut input = String::new();
+    let read = std::io::stdin().read_line(&mut input).context("Failed to read from stdin")?;
+    Ok((read > 0).then(|| input.trim().to_lowercase()))
+}
@@ -1,47 +1,198 @@
 use crate::ai::AiOracle;
-use crate::config::{Config, Rule};
-use crate::journal::{JournalEntry, OpType, Operation};
-use chrono::{Duration, Utc};
+use crate::config::{Action, AgeBasis, Condition, Config, ConflictStrategy, DedupMode, ExistingDedup, Rule};
+use crate::dedup;
+use crate::cache::{mtime_ns, HashCache};
+use crate::index::TargetIndex;
+use crate::journal::{self, JournalEntry, OpType, Operation, Session};
+use crate::phash;
+use crate::archive::{self, ExtractLimits, MemberCache};
+use crate::scanner::Scanner;
+use crate::trash::Trash;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use fs_extra::file::move_file;
 use fs_extra::file::CopyOptions;
+use anyhow::Context;
+use globset::{GlobBuilder, GlobMatcher};
 use rayon::prelude::*;
 use regex::Regex;
-use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use walkdir::WalkDir;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Values accepted by the `type` matcher.
+pub(crate) const TYPE_PRESETS: &[&str] = &["image", "video", "audio", "document"];
 
 pub struct Engine {
     config: Arc<Config>,
     base_dir: PathBuf,
     ai: Arc<Option<AiOracle>>,
+    scanner: Scanner,
+    patterns: Patterns,
+    /// The home trash, or why it could not be located
+    trash: anyhow::Result<Trash>,
+    /// `cache_dir` from the config, or the per-user cache directory
+    cache_dir: Option<PathBuf>,
+    hash_cache: Option<HashCache>,
+    /// Size and modification time of each duplicate when it was analyzed
+    analyzed: Mutex<HashMap<PathBuf, (u64, i64)>>,
+    /// Perceptual hashes of the images in the target directories, loaded on first use
+    existing_images: OnceLock<Vec<(PathBuf, u64)>>,
+    archive_members: MemberCache,
+}
+
+/// Regexes and globs of every rule, compiled once and looked up by their source text.
+#[derive(Default)]
+struct Patterns {
+    regexes: HashMap<String, Regex>,
+    globs: HashMap<String, GlobMatcher>,
+}
+
+impl Patterns {
+    fn compile(config: &Config) -> anyhow::Result<Self> {
+        let mut patterns = Self::default();
+        for rule in &config.rules {
+            let context = || format!("Invalid rule '{}'", rule.name);
+            for regex in [&rule.regex, &rule.exclude_regex].into_iter().flatten() {
+                patterns.add_regex(regex).with_context(context)?;
+            }
+            if let Some(glob) = &rule.not_path {
+                patterns.add_glob(glob).with_context(context)?;
+            }
+            check_ages(rule.older_than.as_deref(), rule.newer_than.as_deref()).with_context(context)?;
+            let conditions = rule.all.iter().flatten().chain(rule.any.iter().flatten()).chain(rule.not.as_deref());
+            for condition in conditions {
+                patterns.add_condition(condition).with_context(context)?;
+            }
+        }
+        Ok(patterns)
+    }
+
+    fn add_condition(&mut self, condition: &Condition) -> anyhow::Result<()> {
+        if let Some(regex) = &condition.regex {
+            self.add_regex(regex)?;
+        }
+        if let Some(glob) = &condition.path {
+            self.add_glob(glob)?;
+        }
+        check_ages(condition.older_than.as_deref(), condition.newer_than.as_deref())?;
+        let children = condition.all.iter().flatten().chain(condition.any.iter().flatten()).chain(condition.not.as_deref());
+        for child in children {
+            self.add_condition(child)?;
+        }
+        Ok(())
+    }
+
+    fn add_regex(&mut self, pattern: &str) -> anyhow::Result<()> {
+        if !self.regexes.contains_key(pattern) {
+            let regex = Regex::new(pattern).with_context(|| format!("regex {:?} does not compile", pattern))?;
+            self.regexes.insert(pattern.to_string(), regex);
+        }
+        Ok(())
+    }
+
+    fn add_glob(&mut self, pattern: &str) -> anyhow::Result<()> {
+        if !self.globs.contains_key(pattern) {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("glob {:?} does not compile", pattern))?;
+            self.globs.insert(pattern.to_string(), glob.compile_matcher());
+        }
+        Ok(())
+    }
+}
+
+/// Combinations that cannot be carried out safely, refused before any file is touched.
+fn check_rule(rule: &Rule) -> anyhow::Result<()> {
+    if rule.action == Some(Action::Extract) && matches!(rule.conflict, Some(ConflictStrategy::Overwrite)) {
+        anyhow::bail!("conflict = \"overwrite\" is not supported by the extract action");
+    }
+    // Without it every image would fail to hash and silently never look similar
+    if rule.similar_to_existing.is_some() && !cfg!(feature = "phash") {
+        anyhow::bail!("similar_to_existing needs rarch built with the `phash` feature");
+    }
+    Ok(())
+}
+
+/// Ages are not compiled, but one that cannot be turned into a point in time
+/// would silently disable its filter.
+fn check_ages(older_than: Option<&str>, newer_than: Option<&str>) -> anyhow::Result<()> {
+    for age in [older_than, newer_than].into_iter().flatten() {
+        if Engine::age_cutoff(age, Utc::now()).is_none() {
+            anyhow::bail!("age {:?} is not a valid age or date, or is out of range", age);
+        }
+    }
+    Ok(())
 }
 
 impl Engine {
-    pub fn new(config: Config, base_dir: PathBuf) -> Self {
+    /// Builds an engine for `base_dir`. Fails if any rule contains a pattern, age or setting that cannot be used.
+    pub fn new(config: Config, base_dir: PathBuf) -> anyhow::Result<Self> {
         let ai = Arc::new(if config.ai_api_base.is_empty() {
             None
         } else {
             Some(AiOracle::new(config.ai_api_base.clone(), config.ai_model.clone()))
         });
-        Self {
+        if let Some(threshold) = config.similarity_threshold.filter(|t| *t > 64) {
+            anyhow::bail!("similarity_threshold {} is out of range (0-64)", threshold);
+        }
+        for rule in &config.rules {
+            check_rule(rule).with_context(|| format!("Invalid rule '{}'", rule.name))?;
+        }
+        let trash = Trash::home();
+        if let Err(e) = &trash {
+            if let Some(rule) = config.rules.iter().find(|r| match r.action {
+                Some(Action::Trash) => true,
+                Some(Action::Extract) => r.delete_source,
+                _ => false,
+            }) {
+                anyhow::bail!("Rule '{}' needs the trash, which is unavailable: {:#}", rule.name, e);
+            }
+        }
+        let scanner = Scanner::new(&config, &base_dir)?;
+        let patterns = Patterns::compile(&config)?;
+        let cache_dir = config.cache_dir.clone().or_else(crate::cache::cache_dir);
+        let hash_cache = match &cache_dir {
+            Some(dir) if config.hash_cache => Some(HashCache::load(HashCache::path_in(dir))),
+            _ => None,
+        };
+        Ok(Self {
             config: Arc::new(config),
             base_dir,
             ai,
-        }
+            scanner,
+            patterns,
+            trash,
+            cache_dir,
+            hash_cache,
+            analyzed: Mutex::new(HashMap::new()),
+            existing_images: OnceLock::new(),
+            archive_members: MemberCache::default(),
+        })
+    }
+
+    /// Uses `trash` instead of the home trash.
+    #[cfg(test)]
+    pub(crate) fn with_trash(mut self, trash: Trash) -> Self {
+        self.trash = Ok(trash);
+        self
+    }
+
+    /// The trash files are sent to, or why there is none.
+    fn trash(&self) -> anyhow::Result<&Trash> {
+        self.trash.as_ref().map_err(|e| anyhow::anyhow!("No trash directory available: {:#}", e))
     }
 
     pub fn process_single_file<F>(&self, path: PathBuf, reporter: Option<F>) -> anyhow::Result<Option<Operation>> 
     where F: Fn(&str) + Clone
     {
-        if !path.is_file() {
+        if !path.is_file() || !self.scanner.accepts(&path) {
             return Ok(None);
         }
 
         if let Some(rule) = self.match_rule(&path, reporter.clone()) {
-            let target_path = self.resolve_target_path(rule, &path, reporter);
+            let target_path = self.planned_target(rule, &path, reporter);
 
             // Avoid moving if it's already in the right place
             if path == target_path {
@@ -51,13 +202,33 @@ impl Engine {
             return Ok(Some(Operation {
                 from: path,
                 to: target_path,
-                op_type: OpType::Move, // In watch mode, we simplify to Move for now
+                op_type: Self::action_op_type(rule), // Watch mode does not deduplicate
                 rule_name: Some(rule.name.clone()),
             }));
         }
         Ok(None)
     }
 
+    /// Where a matched file is expected to end up. Trashed files go to the
+    /// trash; the final name inside it is only known once the file is trashed.
+    /// For archived files this is the archive itself, for extracted ones the
+    /// directory they are unpacked into.
+    fn planned_target<F>(&self, rule: &Rule, path: &Path, reporter: Option<F>) -> PathBuf
+    where F: Fn(&str) + Clone
+    {
+        match rule.action.unwrap_or_default() {
+            Action::Trash => {
+                let file_name = path.file_name().unwrap_or_default();
+                match self.trash() {
+                    Ok(trash) => trash.files_dir().join(file_name),
+                    Err(_) => path.to_path_buf(),
+                }
+            }
+            Action::Archive | Action::Extract => self.base_dir.join(self.resolve_placeholders(rule, path, reporter)),
+            _ => self.resolve_target_path(rule, path, reporter),
+        }
+    }
+
     fn resolve_target_path<F>(&self, rule: &Rule, path: &Path, reporter: Option<F>) -> PathBuf 
     where F: Fn(&str) + Clone
     {
@@ -89,14 +260,25 @@ impl Engine {
     {
         let mut resolved = rule.target.clone();
         
-        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut stem = path.file_stem().unwrap_or_default().to_string_lossy();
         let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        if rule.action == Some(Action::Extract) {
+            // "photos.tar.gz" unpacks into "photos", not "photos.tar"
+            if let Some(inner) = stem.strip_suffix(".tar").filter(|s| !s.is_empty()) {
+                stem = inner.to_string().into();
+            }
+        }
 
         // Basic filename placeholders
         resolved = resolved.replace("${name}", &stem);
         resolved = resolved.replace("${filename}", &filename);
 
         // Check if we need AI renaming
+        #[cfg(not(feature = "ai"))]
+        {
+            resolved = resolved.replace("${ai_name}", &stem);
+        }
+        #[cfg(feature = "ai")]
         if resolved.contains("${ai_name}") {
             if let (Some(filename_str), Some(ai_oracle)) = (path.file_name().and_then(|s| s.to_str()), self.ai.as_ref()) {
                 let context = rule.ai_rename_prompt.as_deref()
@@ -175,42 +357,26 @@ impl Engine {
         resolved
     }
 
-    fn calculate_hash(path: &Path) -> anyhow::Result<String> {
-        let mut file = std::fs::File::open(path)?;
-        let mut hasher = Sha256::new();
-        let mut buffer = [0; 4096];
-        loop {
-            let n = file.read(&mut buffer)?;
-            if n == 0 {
-                break;
-            }
-            hasher.update(&buffer[..n]);
-        }
-        Ok(hex::encode(hasher.finalize()))
-    }
-
     pub fn dry_run<F>(&self, on_progress: F) -> anyhow::Result<Vec<Operation>>
     where
         F: Fn(usize, usize, String) + Send + Sync + Clone,
     {
-        let files: Vec<PathBuf> = WalkDir::new(&self.base_dir)
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .map(|e| e.path().to_path_buf())
-            .collect();
+        self.plan(self.scanner.scan(), on_progress)
+    }
 
+    /// Plans the operations for `files`, including deduplication among them
+    /// and against the target directories.
+    fn plan<F>(&self, files: Vec<PathBuf>, on_progress: F) -> anyhow::Result<Vec<Operation>>
+    where
+        F: Fn(usize, usize, String) + Send + Sync + Clone,
+    {
         let total = files.len();
         let current = Arc::new(Mutex::new(0));
 
-        let seen_hashes: Arc<Mutex<HashMap<String, PathBuf>>> =
-            Arc::new(Mutex::new(HashMap::new()));
-        
         // We need to clone the closure if we want to use it in into_par_iter
         // but closures usually aren't Clone. Instead, use a shared wrapper.
 
-        let ops: Vec<Operation> = files
+        let mut ops: Vec<Operation> = files
             .into_par_iter()
             .enumerate()
             .filter_map(|(_idx, path)| {
@@ -225,25 +391,12 @@ impl Engine {
                 };
                 
                 let res = if let Some(rule) = self.match_rule(&path, Some(reporter.clone())) {
-                    let target_path = self.resolve_target_path(rule, &path, Some(reporter));
-
-                    // Deduplication Logic
-                    let op_type = if let Ok(hash) = Self::calculate_hash(&path) {
-                        let mut hashes = seen_hashes.lock().unwrap();
-                        if let Some(original_target) = hashes.get(&hash) {
-                            OpType::HardLink(original_target.clone())
-                        } else {
-                            hashes.insert(hash, target_path.clone());
-                            OpType::Move
-                        }
-                    } else {
-                        OpType::Move
-                    };
+                    let target_path = self.planned_target(rule, &path, Some(reporter));
 
                     Some(Operation {
                         from: path.clone(),
                         to: target_path,
-                        op_type,
+                        op_type: Self::action_op_type(rule),
                         rule_name: Some(rule.name.clone()),
                     })
                 } else {
@@ -258,19 +411,261 @@ impl Engine {
             })
             .collect();
 
+        if let Some(mode) = self.config.dedup_existing {
+            on_progress(total, total, "Checking target directories for existing copies".to_string());
+            self.mark_existing_twins(&mut ops, mode)?;
+        }
+
+        // Report mode lists the groups separately through `duplicate_groups`
+        let mode = self.config.dedup;
+        if !matches!(mode, DedupMode::Off | DedupMode::Report) {
+            on_progress(total, total, "Looking for duplicates".to_string());
+
+            // Taken before hashing, so a file modified from here on is noticed before it is removed
+            let snapshot: HashMap<PathBuf, (u64, i64)> = ops
+                .iter()
+                .filter(|op| op.op_type == OpType::Move)
+                .filter_map(|op| {
+                    let metadata = std::fs::metadata(&op.from).ok()?;
+                    Some((op.from.clone(), (metadata.len(), mtime_ns(&metadata))))
+                })
+                .collect();
+
+            let targets: HashMap<&Path, &Path> = ops.iter().map(|op| (op.from.as_path(), op.to.as_path())).collect();
+            let mut duplicates: HashMap<PathBuf, PathBuf> = HashMap::new();
+            let mut analyzed = self.analyzed.lock().unwrap();
+            for group in self.duplicate_groups(&ops) {
+                let original_target = targets[group[0].as_path()].to_path_buf();
+                for duplicate in &group[1..] {
+                    duplicates.insert(duplicate.clone(), original_target.clone());
+                    if let Some(state) = snapshot.get(duplicate) {
+                        analyzed.insert(duplicate.clone(), *state);
+                    }
+                }
+            }
+            drop(analyzed);
+            for op in &mut ops {
+                if let Some(original_target) = duplicates.remove(&op.from) {
+                    op.op_type = match mode {
+                        DedupMode::Reflink => OpType::Reflink(original_target),
+                        DedupMode::Symlink => OpType::SoftLink(original_target),
+                        DedupMode::Delete => {
+                            op.to = original_target.clone();
+                            OpType::Deleted(original_target)
+                        }
+                        _ => OpType::HardLink(original_target),
+                    };
+                }
+            }
+            // Duplicates can only be resolved once the originals are in place
+            ops.sort_by_key(|op| op.op_type.duplicate_of().is_some());
+        }
+
+        if let Err(e) = self.save_hash_cache() {
+            on_progress(total, total, format!("Warning: {:#}", e));
+        }
         Ok(ops)
     }
 
-    pub fn execute<F>(&self, journal_path: Option<PathBuf>, mut on_progress: F) -> anyhow::Result<JournalEntry>
+    /// Sets of identical files among the planned moves (only files that leave
+    /// their source behind are candidates). The first path of each set is the original.
+    pub fn duplicate_groups(&self, ops: &[Operation]) -> Vec<Vec<PathBuf>> {
+        let candidates: Vec<PathBuf> = ops
+            .iter()
+            .filter(|op| op.op_type == OpType::Move)
+            .map(|op| op.from.clone())
+            .collect();
+        dedup::find_duplicates(&candidates, |path| self.hash(path))
+    }
+
+    /// Sets of identical files anywhere in the scanned tree, regardless of the
+    /// rules. Paths that are already hard links to each other count as one.
+    pub fn scan_duplicates(&self) -> Vec<Vec<PathBuf>> {
+        let files = self.scanner.scan();
+        dedup::find_duplicates(&files, |path| self.hash(path))
+            .into_iter()
+            .filter_map(|group| {
+                let mut distinct: Vec<PathBuf> = Vec::new();
+                for path in group {
+                    if !distinct.iter().any(|kept| same_file(kept, &path)) {
+                        distinct.push(path);
+                    }
+                }
+                (distinct.len() > 1).then_some(distinct)
+            })
+            .collect()
+    }
+
+    /// Hex SHA-256 of a file, through the hash cache when it is enabled.
+    fn hash(&self, path: &Path) -> std::io::Result<String> {
+        match &self.hash_cache {
+            Some(cache) => cache.hash(path),
+            None => dedup::hash_file(path),
+        }
+    }
+
+    /// Persists hashes computed since the last save.
+    pub fn save_hash_cache(&self) -> anyhow::Result<()> {
+        match &self.hash_cache {
+            Some(cache) => cache.save().context("Failed to save the hash cache"),
+            None => Ok(()),
+        }
+    }
+
+    /// Looks up moved files in the target index and turns those that are
+    /// already organized into `TwinLink` or `AlreadyPresent` operations. A
+    /// file is only compared with what lives below its own rule's target.
+    fn mark_existing_twins(&self, ops: &mut [Operation], mode: ExistingDedup) -> anyhow::Result<()> {
+        let index_path = self.target_index_path()?;
+        let mut target_index = TargetIndex::load(&index_path);
+        target_index.refresh(&self.target_roots());
+
+        let mut candidates: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for op in ops.iter().filter(|op| op.op_type == OpType::Move) {
+            let rule = self.config.rules.iter().find(|rule| op.rule_name.as_ref() == Some(&rule.name));
+            if let Some(root) = rule.and_then(|rule| self.target_root(rule)) {
+                candidates.entry(root).or_default().push(op.from.clone());
+            }
+        }
+        // Taken before hashing, so a file modified from here on is noticed before it is linked
+        let snapshot: HashMap<PathBuf, (u64, i64)> = candidates
+            .values()
+            .flatten()
+            .filter_map(|path| {
+                let metadata = std::fs::metadata(path).ok()?;
+                Some((path.clone(), (metadata.len(), mtime_ns(&metadata))))
+            })
+            .collect();
+        let mut twins = HashMap::new();
+        for (root, files) in &candidates {
+            twins.extend(target_index.find_twins(files, root, |path| self.hash(path)));
+        }
+        target_index
+            .save(&index_path)
+            .with_context(|| format!("Failed to save the target index {:?}", index_path))?;
+
+        let mut analyzed = self.analyzed.lock().unwrap();
+        for op in ops.iter_mut() {
+            if let Some(twin) = twins.remove(&op.from) {
+                op.op_type = match mode {
+                    ExistingDedup::Hardlink if !same_file(&op.from, &twin) => OpType::TwinLink,
+                    _ => OpType::AlreadyPresent,
+                };
+                if let Some(state) = snapshot.get(&op.from).filter(|_| op.op_type == OpType::TwinLink) {
+                    analyzed.insert(op.from.clone(), *state);
+                }
+                op.to = twin;
+            }
+        }
+        Ok(())
+    }
+
+    /// A relative `dedup_index` was resolved against the configuration file when it was loaded.
+    fn target_index_path(&self) -> anyhow::Result<PathBuf> {
+        match (&self.config.dedup_index, &self.cache_dir) {
+            (Some(path), _) => Ok(path.clone()),
+            (None, Some(dir)) => Ok(TargetIndex::path_in(dir)),
+            (None, None) => anyhow::bail!("Cannot locate the target index: $HOME is not set"),
+        }
+    }
+
+    /// Canonical target directories of the `move` rules, where organized files live.
+    fn target_roots(&self) -> Vec<PathBuf> {
+        let mut roots: Vec<PathBuf> = Vec::new();
+        for rule in &self.config.rules {
+            if let Some(root) = self.target_root(rule) {
+                if !roots.contains(&root) {
+                    roots.push(root);
+                }
+            }
+        }
+        roots
+    }
+
+    /// Canonical static part of a `move` rule's target, if it exists yet.
+    fn target_root(&self, rule: &Rule) -> Option<PathBuf> {
+        if rule.action.unwrap_or_default() != Action::Move {
+            return None;
+        }
+        let root = crate::scanner::target_root(&rule.target)?;
+        self.base_dir.join(root).canonicalize().ok()
+    }
+
+    /// Perceptual hashes of the images already organized, through the target index.
+    fn existing_images(&self) -> &[(PathBuf, u64)] {
+        self.existing_images.get_or_init(|| {
+            let Ok(index_path) = self.target_index_path() else {
+                return Vec::new();
+            };
+            let mut target_index = TargetIndex::load(&index_path);
+            target_index.refresh(&self.target_roots());
+            let hashes = target_index.image_hashes();
+            // The index is only a cache; failing to write it costs time, not correctness
+            let _ = target_index.save(&index_path);
+            hashes
+        })
+    }
+
+    /// True if the image looks like one already in a target directory.
+    fn similar_to_existing(&self, facts: &FileFacts) -> bool {
+        if !facts.detected_mime.as_deref().is_some_and(|mime| mime.starts_with("image/")) {
+            return false;
+        }
+        let Ok(hash) = phash::dhash(facts.path) else {
+            return false;
+        };
+        let threshold = self.config.similarity_threshold.unwrap_or(phash::DEFAULT_THRESHOLD);
+        self.existing_images()
+            .iter()
+            .any(|(path, existing)| phash::distance(hash, *existing) <= threshold && !same_file(path, facts.path))
+    }
+
+    /// Groups of images below the base directory that look alike, regardless of the rules.
+    pub fn scan_similar_images(&self, threshold: u32) -> Vec<Vec<PathBuf>> {
+        let hashes: Vec<(PathBuf, u64)> = self
+            .scanner
+            .scan()
+            .into_par_iter()
+            .filter(|path| phash::is_image(path))
+            .filter_map(|path| phash::dhash(&path).ok().map(|hash| (path, hash)))
+            .collect();
+        phash::group_similar(&hashes, threshold)
+    }
+
+    pub fn execute<F>(&self, session: Option<&Session>, mut on_progress: F) -> anyhow::Result<JournalEntry>
     where
         F: FnMut(usize, usize, String),
     {
+        anyhow::ensure!(
+            self.config.dedup != DedupMode::Report,
+            "dedup = \"report\" only plans and lists duplicates; nothing is changed in this mode"
+        );
         let ops = self.dry_run(|_, _, _| {})?;
         let total = ops.len();
         let mut journal = JournalEntry::new();
-        let options = CopyOptions::new();
+        // Planned target of every moved file -> where it actually ended up
+        let mut placed: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+        for (i, mut op) in ops.into_iter().enumerate() {
+            if op.op_type == OpType::AlreadyPresent {
+                on_progress(i + 1, total, format!("Skipped (Already present): {:?} as {:?}", op.from.file_name().unwrap(), op.to));
+                continue;
+            }
+
+            // Duplicates follow their original, which may have been renamed or not moved at all
+            if let OpType::HardLink(original) | OpType::Reflink(original) | OpType::SoftLink(original) | OpType::Deleted(original) =
+                &mut op.op_type
+            {
+                let Some(final_original) = placed.get(original.as_path()) else {
+                    on_progress(i + 1, total, format!("Skipped (Original not organized): {:?}", op.from.file_name().unwrap()));
+                    continue;
+                };
+                *original = final_original.clone();
+                if matches!(op.op_type, OpType::Deleted(_)) {
+                    op.to = final_original.clone();
+                }
+            }
 
-        for (i, op) in ops.into_iter().enumerate() {
             let target_parent = op.to.parent().expect("Target path has no parent");
             if !target_parent.exists() {
                 if let Err(e) = std::fs::create_dir_all(target_parent) {
@@ -291,53 +686,290 @@ impl Engine {
                 }
             };
 
-            let op_result = match &op.op_type {
-                OpType::Move => move_file(&op.from, &final_to, &options).map(|_| ()),
-                OpType::HardLink(original_path) => {
-                    if op.from.exists() {
-                        let res = std::fs::remove_file(&op.from)
-                            .and_then(|_| std::fs::hard_link(original_path, &final_to));
-                        res.map_err(fs_extra::error::Error::from)
-                    } else {
-                        Ok(())
-                    }
-                }
-            };
+            let op_result = self.apply_journaled(&op, &final_to, session);
 
             match op_result {
-                Ok(_) => {
-                    let mut final_op = op;
-                    final_op.to = final_to;
-                    on_progress(i + 1, total, format!("Done: {:?}", final_op.from.file_name().unwrap()));
-                    
-                    // Atomic-like append to file
-                    if let Some(path) = &journal_path {
-                        let _ = JournalEntry::append_to_file(path, &final_op);
+                Ok(final_ops) => {
+                    on_progress(i + 1, total, format!("Done: {:?}", op.from.file_name().unwrap()));
+                    if op.op_type == OpType::Move {
+                        placed.insert(op.to.clone(), final_to.clone());
                     }
-                    journal.operations.push(final_op);
+                    
+                    journal.operations.extend(final_ops);
                 }
                 Err(e) => {
-                    on_progress(i + 1, total, format!("Error (Move): {:?} -> {:?} : {}", op.from, final_to, e));
+                    on_progress(i + 1, total, format!("Error ({:?}): {:?} -> {:?} : {:#}", op.op_type, op.from, final_to, e));
                 }
             }
         }
 
+        if let Err(e) = self.save_hash_cache() {
+            on_progress(total, total, format!("Warning: {:#}", e));
+        }
         Ok(journal)
     }
 
+    fn action_op_type(rule: &Rule) -> OpType {
+        match rule.action.unwrap_or_default() {
+            Action::Move => OpType::Move,
+            Action::Copy => OpType::Copy,
+            Action::Symlink => OpType::Symlink,
+            Action::Hardlink => OpType::Link,
+            Action::Trash => OpType::Trash(PathBuf::new()),
+            Action::Archive => OpType::Archive { archive: PathBuf::new(), member: String::new() },
+            Action::Extract => OpType::Extract,
+        }
+    }
+
+    /// Like `apply`, but journaled in two phases: the intent is on disk before
+    /// any file is touched, and the commit once the change itself is synced.
+    /// A crash in between leaves the intent for `rarch recover`.
+    pub fn apply_journaled(&self, op: &Operation, final_to: &Path, session: Option<&Session>) -> anyhow::Result<Vec<Operation>> {
+        let Some(session) = session else {
+            return self.apply(op, final_to);
+        };
+        let mut planned = op.clone();
+        planned.to = final_to.to_path_buf();
+        let id = session.intent(&planned)?;
+        // Extracted files are not known in advance, so each one is journaled before it is created
+        let result = match op.op_type {
+            OpType::Extract => self.extract(op, final_to, &mut |path| session.created(id, path)),
+            _ => self.apply(op, final_to),
+        };
+        match result {
+            Ok(done) => {
+                journal::sync_parent(&op.from);
+                for done_op in &done {
+                    journal::sync_parent(&done_op.to);
+                }
+                session
+                    .commit(id, &done)
+                    .with_context(|| format!("{:?} was processed but could not be journaled (see 'rarch recover')", op.from))?;
+                Ok(done)
+            }
+            Err(e) => {
+                let _ = session.abort(id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Performs a planned operation, placing the result at `final_to`.
+    /// Returns the operations as they should be journaled: usually just the
+    /// one, but one per file for extractions.
+    pub fn apply(&self, op: &Operation, final_to: &Path) -> anyhow::Result<Vec<Operation>> {
+        let mut done = op.clone();
+        done.to = final_to.to_path_buf();
+        match &op.op_type {
+            OpType::Move => {
+                move_file(&op.from, final_to, &CopyOptions::new())
+                    .with_context(|| format!("Failed to move {:?} to {:?}", op.from, final_to))?;
+            }
+            OpType::Copy => {
+                std::fs::copy(&op.from, final_to)
+                    .with_context(|| format!("Failed to copy {:?} to {:?}", op.from, final_to))?;
+            }
+            OpType::Symlink => {
+                // Link to the absolute source so the link works from any directory
+                let source = op.from.canonicalize()?;
+                link_over(final_to, |at| symlink_file(&source, at))
+                    .with_context(|| format!("Failed to symlink {:?} at {:?}", source, final_to))?;
+            }
+            OpType::Link => {
+                link_over(final_to, |at| std::fs::hard_link(&op.from, at))
+                    .with_context(|| format!("Failed to hard link {:?} at {:?}", op.from, final_to))?;
+            }
+            OpType::HardLink(original_path) => {
+                self.verify_duplicate(&op.from, original_path)?;
+                // Link before unlinking, so a failed link (e.g. across file systems) loses nothing
+                std::fs::hard_link(original_path, final_to)
+                    .with_context(|| format!("Failed to deduplicate {:?} against {:?}", op.from, original_path))?;
+                std::fs::remove_file(&op.from)
+                    .with_context(|| format!("Linked {:?} but failed to remove it", op.from))?;
+            }
+            OpType::Reflink(original_path) => {
+                self.verify_duplicate(&op.from, original_path)?;
+                // The clone is made first, so a file system without reflinks leaves the duplicate untouched
+                reflink_file(original_path, final_to)
+                    .with_context(|| format!("Failed to reflink {:?} at {:?}", original_path, final_to))?;
+                std::fs::remove_file(&op.from)
+                    .with_context(|| format!("Reflinked {:?} but failed to remove it", op.from))?;
+            }
+            OpType::SoftLink(original_path) => {
+                self.verify_duplicate(&op.from, original_path)?;
+                let original = original_path.canonicalize()?;
+                symlink_file(&original, final_to)
+                    .with_context(|| format!("Failed to symlink {:?} at {:?}", original, final_to))?;
+                std::fs::remove_file(&op.from)
+                    .with_context(|| format!("Linked {:?} but failed to remove it", op.from))?;
+            }
+            OpType::Deleted(original_path) => {
+                self.verify_duplicate(&op.from, original_path)?;
+                std::fs::remove_file(&op.from).with_context(|| format!("Failed to delete {:?}", op.from))?;
+            }
+            OpType::Trash(_) => {
+                let trash = self.trash()?;
+                let (trashed_path, info_path) = trash.put(&op.from)?;
+                done.to = trashed_path;
+                done.op_type = OpType::Trash(info_path);
+            }
+            OpType::Archive { .. } => {
+                // The source is only removed once the archived copy has been verified
+                let member = archive::append(final_to, &op.from, &self.archive_members)?;
+                std::fs::remove_file(&op.from)
+                    .with_context(|| format!("Archived {:?} but failed to remove it", op.from))?;
+                done.op_type = OpType::Archive { archive: final_to.to_path_buf(), member };
+            }
+            OpType::Extract => return self.extract(op, final_to, &mut |_| Ok(())),
+            OpType::TwinLink => {
+                self.verify_duplicate(&op.from, final_to)?;
+                // Link under a temporary name first so `from` is never missing
+                let tmp = link_temp(&op.from);
+                std::fs::hard_link(final_to, &tmp)
+                    .and_then(|_| std::fs::rename(&tmp, &op.from))
+                    .with_context(|| format!("Failed to replace {:?} with a link to {:?}", op.from, final_to))?;
+            }
+            OpType::AlreadyPresent => {}
+        }
+        Ok(vec![done])
+    }
+
+    /// Re-checks a duplicate right before it is removed: it must be unchanged
+    /// since it was analyzed and identical to `original` byte for byte.
+    pub(crate) fn verify_duplicate(&self, duplicate: &Path, original: &Path) -> anyhow::Result<()> {
+        let metadata = std::fs::metadata(duplicate).with_context(|| format!("Cannot read {:?}", duplicate))?;
+        if let Some(&(size, mtime)) = self.analyzed.lock().unwrap().get(duplicate) {
+            anyhow::ensure!(
+                metadata.len() == size && mtime_ns(&metadata) == mtime,
+                "{:?} changed since it was analyzed, keeping it",
+                duplicate
+            );
+        }
+        let identical = dedup::same_content(duplicate, original)
+            .with_context(|| format!("Cannot compare {:?} with {:?}", duplicate, original))?;
+        anyhow::ensure!(identical, "{:?} differs from {:?}, keeping it", duplicate, original);
+        Ok(())
+    }
+
+    fn extract(
+        &self,
+        op: &Operation,
+        dest: &Path,
+        on_create: &mut dyn FnMut(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Vec<Operation>> {
+        let rule = self.config.rules.iter().find(|r| Some(&r.name) == op.rule_name.as_ref());
+        let conflict = rule.and_then(|r| r.conflict.clone()).unwrap_or_default();
+        let delete_source = rule.is_some_and(|r| r.delete_source);
+        let mut limits = ExtractLimits::default();
+        if let Some(max) = rule.and_then(|r| r.max_extract_size) {
+            limits.max_bytes = max;
+        }
+        let trash = match delete_source {
+            true => Some(self.trash().context("Cannot honour delete_source")?),
+            false => None,
+        };
+
+        let extracted = archive::extract(&op.from, dest, &conflict, &limits, on_create)?;
+        // Directories come first, so undoing in reverse empties them before removing them
+        let mut done: Vec<Operation> = extracted
+            .dirs
+            .iter()
+            .chain(&extracted.files)
+            .map(|file| Operation {
+                from: op.from.clone(),
+                to: file.clone(),
+                op_type: OpType::Extract,
+                rule_name: op.rule_name.clone(),
+            })
+            .collect();
+
+        if let Some(trash) = trash {
+            match trash.put(&op.from) {
+                Ok((trashed_path, info_path)) => done.push(Operation {
+                    from: op.from.clone(),
+                    to: trashed_path,
+                    op_type: OpType::Trash(info_path),
+                    rule_name: op.rule_name.clone(),
+                }),
+                Err(e) => {
+                    // Keep the result all-or-nothing
+                    extracted.remove();
+                    return Err(e.context("Extracted, but failed to trash the archive; extraction rolled back"));
+                }
+            }
+        }
+        Ok(done)
+    }
+
+    /// True if `existing` already is the result of applying `op`, so a
+    /// copy/link action that runs again does not create another duplicate.
+    fn already_applied(&self, op: &Operation, existing: &Path) -> bool {
+        match op.op_type {
+            OpType::Symlink => match (std::fs::read_link(existing), op.from.canonicalize()) {
+                (Ok(link), Ok(source)) => link == source,
+                _ => false,
+            },
+            OpType::Link => same_file(&op.from, existing),
+            OpType::Copy => {
+                let same_size = match (std::fs::metadata(&op.from), std::fs::metadata(existing)) {
+                    (Ok(a), Ok(b)) => a.len() == b.len(),
+                    _ => false,
+                };
+                same_size
+                    && matches!(
+                        (self.hash(&op.from), self.hash(existing)),
+                        (Ok(a), Ok(b)) if a == b
+                    )
+            }
+            OpType::Move
+            | OpType::HardLink(_)
+            | OpType::Reflink(_)
+            | OpType::SoftLink(_)
+            | OpType::Deleted(_)
+            | OpType::Trash(_)
+            | OpType::Archive { .. }
+            | OpType::Extract
+            | OpType::TwinLink
+            | OpType::AlreadyPresent => false,
+        }
+    }
+
     pub(crate) fn handle_conflict(&self, op: &Operation) -> anyhow::Result<Option<PathBuf>> {
-        if !op.to.exists() {
+        // The trash picks a free name on its own, extraction resolves clashes per file,
+        // and twins and the originals of deleted duplicates are expected to exist
+        if matches!(
+            op.op_type,
+            OpType::Trash(_) | OpType::Extract | OpType::TwinLink | OpType::AlreadyPresent | OpType::Deleted(_)
+        )
+            || op.to.symlink_metadata().is_err()
+        {
             return Ok(Some(op.to.clone()));
         }
+        if self.already_applied(op, &op.to) {
+            return Ok(None);
+        }
 
-        // We need the rule to check for conflict strategy
-        let rule = self.match_rule::<fn(&str)>(&op.from, None).unwrap();
-        let strategy = rule.conflict.as_ref().cloned().unwrap_or_default();
+        // We need the rule to check for conflict strategy; a redo names it, a run matches it
+        let rule = match &op.rule_name {
+            Some(name) => self.config.rules.iter().find(|rule| &rule.name == name),
+            None => None,
+        }
+        .or_else(|| self.match_rule::<fn(&str)>(&op.from, None));
+        let strategy = rule.and_then(|rule| rule.conflict.clone()).unwrap_or_default();
+
+        // Archives never replace members; a clashing name gets a suffix instead
+        if matches!(op.op_type, OpType::Archive { .. }) {
+            let name = op.from.file_name().unwrap_or_default().to_string_lossy();
+            if matches!(strategy, ConflictStrategy::Skip) && self.archive_members.contains(&op.to, &name)? {
+                return Ok(None);
+            }
+            return Ok(Some(op.to.clone()));
+        }
 
         match strategy {
-            crate::config::ConflictStrategy::Skip => Ok(None),
-            crate::config::ConflictStrategy::Overwrite => Ok(Some(op.to.clone())),
-            crate::config::ConflictStrategy::Rename => {
+            ConflictStrategy::Skip => Ok(None),
+            ConflictStrategy::Overwrite => Ok(Some(op.to.clone())),
+            ConflictStrategy::Rename => {
                 let stem = op.to.file_stem().unwrap().to_str().unwrap();
                 let ext = op.to.extension().and_then(|e| e.to_str()).unwrap_or("");
                 let parent = op.to.parent().unwrap();
@@ -349,18 +981,562 @@ impl Engine {
                         format!("{} ({}).{}", stem, i, ext)
                     };
                     let new_path = parent.join(new_name);
-                    if !new_path.exists() {
+                    if new_path.symlink_metadata().is_err() {
                         return Ok(Some(new_path));
                     }
+                    if self.already_applied(op, &new_path) {
+                        return Ok(None);
+                    }
                 }
                 anyhow::bail!("Too many file name conflicts for {:?}", op.to);
             }
         }
     }
 
+    /// Called by watch mode for every changed path; picks up edits to
+    /// `.rarchignore` files. Returns true if `path` is one.
+    pub fn reload_ignore_file(&self, path: &Path) -> bool {
+        self.scanner.reload_ignore_file(path)
+    }
+
     pub fn match_rule<F>(&self, path: &Path, reporter: Option<F>) -> Option<&Rule> 
     where F: Fn(&str) + Clone
     {
+        let facts = FileFacts::inspect(path)?;
+        self.config
+            .rules
+            .iter()
+            .find(|rule| self.rule_matches(rule, &facts, reporter.clone(), None))
+    }
+
+    /// Evaluates a single rule against a file. When `trace` is given, every
+    /// matcher and filter that was looked at is recorded along with its result.
+    fn rule_matches<F>(&self, rule: &Rule, facts: &FileFacts, reporter: Option<F>, mut trace: Option<&mut Vec<MatchStep>>) -> bool
+    where F: Fn(&str) + Clone
+    {
+        let mut record = |label: String, result: Option<bool>| {
+            if let Some(steps) = trace.as_deref_mut() {
+                steps.push(MatchStep { label, result });
+            }
+            result.unwrap_or(false)
+        };
+        let mut matched = false;
+
+        // Check MIME-based matching (Modern/Robust)
+        if let Some(rule_mime) = &rule.mime {
+            let hit = facts.detected_mime.as_deref().is_some_and(|actual| Self::mime_matches(rule_mime, actual));
+            matched |= record(format!("mime = {:?}", rule_mime), Some(hit));
+        }
+
+        // Check Preset-based matching (User-friendly)
+        if let Some(rule_type) = &rule.r#type {
+            matched |= record(format!("type = {:?}", rule_type), Some(Self::type_matches(rule_type, facts)));
+        }
+
+        // Check Extension-based matching (Classic/Fallback)
+        if let Some(rule_exts) = &rule.extensions {
+            matched |= record(format!("extensions = {:?}", rule_exts), Some(Self::extensions_match(rule_exts, facts)));
+        }
+
+        // Check Regex-based matching (Advanced)
+        if let Some(rule_regex) = &rule.regex {
+            matched |= record(format!("regex = {:?}", rule_regex), Some(self.regex_matches(rule_regex, facts)));
+        }
+
+        // Check AI-based matching (Experimental/Smart). Only asked when nothing cheaper matched.
+        if let Some(ai_prompt) = &rule.ai_prompt {
+            let result = if matched { None } else { Some(self.ai_matches(ai_prompt, facts, reporter.clone())) };
+            matched |= record(format!("ai_prompt = {:?}", ai_prompt), result);
+        }
+
+        // Rules without flat matchers are decided by their condition tree alone
+        let has_flat_matchers = rule.mime.is_some()
+            || rule.r#type.is_some()
+            || rule.extensions.is_some()
+            || rule.regex.is_some()
+            || rule.ai_prompt.is_some();
+        let has_tree = rule.all.is_some() || rule.any.is_some() || rule.not.is_some();
+        if !has_flat_matchers && has_tree {
+            matched = true;
+        }
+
+        if !matched {
+            return false;
+        }
+
+        // Negative matchers veto a positive match
+        if let Some(exclude_mime) = &rule.exclude_mime {
+            let hit = facts.detected_mime.as_deref().is_some_and(|actual| Self::mime_matches(exclude_mime, actual));
+            if record(format!("exclude_mime = {:?} (vetoes)", exclude_mime), Some(hit)) {
+                return false;
+            }
+        }
+        if let Some(exclude_exts) = &rule.exclude_extensions {
+            if record(format!("exclude_extensions = {:?} (vetoes)", exclude_exts), Some(Self::extensions_match(exclude_exts, facts))) {
+                return false;
+            }
+        }
+        if let Some(exclude_regex) = &rule.exclude_regex {
+            if record(format!("exclude_regex = {:?} (vetoes)", exclude_regex), Some(self.regex_matches(exclude_regex, facts))) {
+                return false;
+            }
+        }
+        if let Some(not_path) = &rule.not_path {
+            if record(format!("not_path = {:?} (vetoes)", not_path), Some(self.path_matches(not_path, facts.path))) {
+                return false;
+            }
+        }
+
+        // Apply AND filters (Size, Age)
+        if rule.min_size.is_some() || rule.max_size.is_some() {
+            let label = format!("size {} within [{}, {}]", facts.size, rule.min_size.map_or("-".to_string(), |s| s.to_string()), rule.max_size.map_or("-".to_string(), |s| s.to_string()));
+            if !record(label, Some(Self::size_matches(rule.min_size, rule.max_size, facts))) {
+                return false;
+            }
+        }
+
+        if rule.older_than.is_some() || rule.newer_than.is_some() {
+            let basis = rule.age_basis.unwrap_or_default();
+            let label = format!(
+                "{:?} time: older_than = {:?}, newer_than = {:?}",
+                basis,
+                rule.older_than.as_deref().unwrap_or("-"),
+                rule.newer_than.as_deref().unwrap_or("-")
+            );
+            let hit = Self::age_matches(rule.older_than.as_deref(), rule.newer_than.as_deref(), basis, facts);
+            if !record(label, Some(hit)) {
+                return false;
+            }
+        }
+
+        // Apply the nested condition tree (all/any/not)
+        if let Some(all) = &rule.all {
+            let hit = all.iter().all(|c| self.condition_matches(c, facts, reporter.clone()));
+            if !record(format!("all ({} conditions)", all.len()), Some(hit)) {
+                return false;
+            }
+        }
+        if let Some(any) = &rule.any {
+            let hit = any.iter().any(|c| self.condition_matches(c, facts, reporter.clone()));
+            if !record(format!("any ({} conditions)", any.len()), Some(hit)) {
+                return false;
+            }
+        }
+        if let Some(not) = &rule.not {
+            let hit = !self.condition_matches(not, facts, reporter.clone());
+            if !record("not (condition must fail)".to_string(), Some(hit)) {
+                return false;
+            }
+        }
+
+        // Perceptual comparison is the most expensive filter, so it runs last
+        if let Some(similar) = rule.similar_to_existing {
+            let hit = self.similar_to_existing(facts) == similar;
+            if !record(format!("similar_to_existing = {}", similar), Some(hit)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Evaluates every rule against one file and reports what would happen to it.
+    pub fn explain(&self, path: &Path) -> anyhow::Result<Explanation> {
+        // Spell the path the way a scan of the base directory would
+        let path = &self
+            .scanner
+            .relative(path)
+            .map(|relative| self.base_dir.join(relative))
+            .unwrap_or_else(|| path.to_path_buf());
+        let facts = FileFacts::inspect(path).with_context(|| format!("Cannot read {:?}", path))?;
+        let quiet = |_: &str| {};
+
+        let mut rules = Vec::new();
+        let mut matched_rule: Option<&Rule> = None;
+        for rule in &self.config.rules {
+            let mut steps = Vec::new();
+            let matched = self.rule_matches(rule, &facts, Some(quiet), Some(&mut steps));
+            if matched && matched_rule.is_none() {
+                matched_rule = Some(rule);
+            }
+            rules.push(RuleExplanation {
+                name: rule.name.clone(),
+                steps,
+                matched,
+            });
+        }
+
+        let mut explanation = Explanation {
+            detected_mime: facts.detected_mime.clone(),
+            detected_ext: facts.detected_ext.clone(),
+            file_ext: facts.file_ext.clone(),
+            size: facts.size,
+            in_scan: self.scanner.accepts(path),
+            rules,
+            matched_rule: matched_rule.map(|r| r.name.clone()),
+            target: None,
+            final_target: None,
+            duplicate_of: None,
+        };
+
+        let Some(rule) = matched_rule else {
+            return Ok(explanation);
+        };
+        let target = self.planned_target(rule, path, Some(quiet));
+        let op = Operation {
+            from: path.to_path_buf(),
+            to: target.clone(),
+            op_type: Self::action_op_type(rule),
+            rule_name: Some(rule.name.clone()),
+        };
+        explanation.final_target = Some(self.handle_conflict(&op).map_err(|e| e.to_string()));
+        explanation.target = Some(target);
+
+        if explanation.in_scan {
+            // Only files of the same size can be copies, so plan just those
+            let peers: Vec<PathBuf> = self
+                .scanner
+                .scan()
+                .into_iter()
+                .filter(|peer| std::fs::metadata(peer).is_ok_and(|m| m.len() == facts.size))
+                .collect();
+            let canonical = path.canonicalize()?;
+            let planned = self.plan(peers, |_, _, _| {})?;
+            explanation.duplicate_of = planned
+                .into_iter()
+                .find(|op| op.from.canonicalize().is_ok_and(|p| p == canonical))
+                .and_then(|op| match &op.op_type {
+                    OpType::TwinLink | OpType::AlreadyPresent => Some((op.to, op.op_type)),
+                    op_type => op_type.duplicate_of().map(|original| (original.to_path_buf(), op.op_type.clone())),
+                });
+        }
+
+        Ok(explanation)
+    }
+
+    /// Evaluates one node of a condition tree. Every matcher set on the node must hold.
+    fn condition_matches<F>(&self, condition: &Condition, facts: &FileFacts, reporter: Option<F>) -> bool
+    where F: Fn(&str) + Clone
+    {
+        if let Some(mime) = &condition.mime {
+            if !facts.detected_mime.as_deref().is_some_and(|actual| Self::mime_matches(mime, actual)) {
+                return false;
+            }
+        }
+        if let Some(rule_type) = &condition.r#type {
+            if !Self::type_matches(rule_type, facts) {
+                return false;
+            }
+        }
+        if let Some(exts) = &condition.extensions {
+            if !Self::extensions_match(exts, facts) {
+                return false;
+            }
+        }
+        if let Some(regex) = &condition.regex {
+            if !self.regex_matches(regex, facts) {
+                return false;
+            }
+        }
+        if let Some(glob) = &condition.path {
+            if !self.path_matches(glob, facts.path) {
+                return false;
+            }
+        }
+        if !Self::size_matches(condition.min_size, condition.max_size, facts) {
+            return false;
+        }
+        let basis = condition.age_basis.unwrap_or_default();
+        if !Self::age_matches(condition.older_than.as_deref(), condition.newer_than.as_deref(), basis, facts) {
+            return false;
+        }
+        if let Some(all) = &condition.all {
+            if !all.iter().all(|c| self.condition_matches(c, facts, reporter.clone())) {
+                return false;
+            }
+        }
+        if let Some(any) = &condition.any {
+            if !any.iter().any(|c| self.condition_matches(c, facts, reporter.clone())) {
+                return false;
+            }
+        }
+        if let Some(not) = &condition.not {
+            if self.condition_matches(not, facts, reporter.clone()) {
+                return false;
+            }
+        }
+        // The AI is the most expensive matcher, so it is asked last
+        if let Some(ai_prompt) = &condition.ai_prompt {
+            if !self.ai_matches(ai_prompt, facts, reporter) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn mime_matches(pattern: &str, actual_mime: &str) -> bool {
+        pattern == actual_mime || (pattern.ends_with("/*") && actual_mime.starts_with(&pattern[..pattern.len() - 1]))
+    }
+
+    fn type_matches(rule_type: &str, facts: &FileFacts) -> bool {
+        let Some(actual_mime) = &facts.detected_mime else {
+            return false;
+        };
+        match rule_type {
+            "image" => actual_mime.starts_with("image/"),
+            "video" => actual_mime.starts_with("video/"),
+            "audio" => actual_mime.starts_with("audio/"),
+            "document" => {
+                actual_mime.contains("pdf")
+                    || actual_mime.contains("word")
+                    || actual_mime.contains("text")
+            }
+            _ => false,
+        }
+    }
+
+    fn extensions_match(exts: &[String], facts: &FileFacts) -> bool {
+        exts.iter().any(|e| {
+            let e_low = e.to_lowercase();
+            Some(&e_low) == facts.detected_ext.as_ref() || Some(e_low) == facts.file_ext
+        })
+    }
+
+    fn regex_matches(&self, pattern: &str, facts: &FileFacts) -> bool {
+        let Some(filename) = facts.path.file_name().and_then(|s| s.to_str()) else {
+            return false;
+        };
+        self.patterns.regexes.get(pattern).is_some_and(|re| re.is_match(filename))
+    }
+
+    /// Matches a glob against the path relative to the base directory.
+    fn path_matches(&self, pattern: &str, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.base_dir).unwrap_or(path);
+        self.patterns.globs.get(pattern).is_some_and(|glob| glob.is_match(relative))
+    }
+
+    fn size_matches(min_size: Option<u64>, max_size: Option<u64>, facts: &FileFacts) -> bool {
+        min_size.is_none_or(|min| facts.size >= min) && max_size.is_none_or(|max| facts.size <= max)
+    }
+
+    fn age_matches(older_than: Option<&str>, newer_than: Option<&str>, basis: AgeBasis, facts: &FileFacts) -> bool {
+        if older_than.is_none() && newer_than.is_none() {
+            return true;
+        }
+        let timestamp = match basis {
+            AgeBasis::Modified => facts.metadata.modified(),
+            AgeBasis::Created => facts.metadata.created(),
+            AgeBasis::Accessed => facts.metadata.accessed(),
+        };
+        // Unknown timestamps and unparsable ages do not filter anything out
+        let Ok(timestamp) = timestamp else {
+            return true;
+        };
+        let file_time = DateTime::<Utc>::from(timestamp);
+        let now = Utc::now();
+
+        if let Some(cutoff) = older_than.and_then(|s| Self::age_cutoff(s, now)) {
+            if file_time > cutoff {
+                return false;
+            }
+        }
+        if let Some(cutoff) = newer_than.and_then(|s| Self::age_cutoff(s, now)) {
+            if file_time <= cutoff {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Turns an age ("30d", "1w3d") or an absolute date ("2024-01-31",
+    /// "2024-01-31T08:00:00", RFC 3339) into the point in time it refers to.
+    pub(crate) fn age_cutoff(s: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let s = s.trim();
+        if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+            return Some(dt.with_timezone(&Utc));
+        }
+        if let Ok(naive) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
+            return Local.from_local_datetime(&naive).earliest().map(|dt| dt.with_timezone(&Utc));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            let naive = date.and_hms_opt(0, 0, 0)?;
+            return Local.from_local_datetime(&naive).earliest().map(|dt| dt.with_timezone(&Utc));
+        }
+        Self::parse_age(s).and_then(|duration| now.checked_sub_signed(duration))
+    }
+
+    fn ai_matches<F>(&self, prompt: &str, facts: &FileFacts, reporter: Option<F>) -> bool
+    where F: Fn(&str) + Clone
+    {
+        let (Some(filename), Some(ai_oracle)) = (facts.path.file_name().and_then(|s| s.to_str()), self.ai.as_ref()) else {
+            return false;
+        };
+        // Try to read a snippet of content if it's likely text
+        let content_snippet = if let Ok(mut file) = std::fs::File::open(facts.path) {
+            let mut buffer = [0; 512];
+            if let Ok(n) = file.read(&mut buffer) {
+                if std::str::from_utf8(&buffer[..n]).is_ok() {
+                    Some(String::from_utf8_lossy(&buffer[..n]).to_string())
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        ai_oracle.matches_prompt(filename, content_snippet.as_deref(), prompt, reporter)
+    }
+    /// Parses a relative age made of one or more `<number><unit>` parts, e.g.
+    /// "30d", "1w3d" or "1y6m". Units: `y`, `m` (month), `w`, `d`, `h`, `min`, `s`.
+    pub(crate) fn parse_age(s: &str) -> Option<Duration> {
+        let mut total = Duration::zero();
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return None;
+        }
+        while !rest.is_empty() {
+            let num_len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let unit_len = rest[num_len..]
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len() - num_len);
+            let num = rest[..num_len].parse::<i64>().ok()?;
+            // Out-of-range amounts are rejected rather than overflowing
+            let part = match rest[num_len..num_len + unit_len].to_lowercase().as_str() {
+                "d" => Duration::try_days(num),
+                "w" => Duration::try_weeks(num),
+                "m" => Duration::try_days(num.checked_mul(30)?), // Rough month
+                "y" => Duration::try_days(num.checked_mul(365)?), // Rough year
+                "h" => Duration::try_hours(num),
+                "min" => Duration::try_minutes(num),
+                "s" => Duration::try_seconds(num),
+                _ => return None,
+            };
+            total = total.checked_add(&part?)?;
+            rest = rest[num_len + unit_len..].trim_start();
+        }
+        Some(total)
+    }
+}
+
+#[cfg(unix)]
+fn symlink_file(source: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(source: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(source, link)
+}
+
+/// Creates `dest` as a copy-on-write clone of `source` (btrfs, xfs and other
+/// file systems supporting `FICLONE`).
+#[cfg(target_os = "linux")]
+fn reflink_file(source: &Path, dest: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let src = std::fs::File::open(source)?;
+    let dst = std::fs::OpenOptions::new().write(true).create_new(true).open(dest)?;
+    // SAFETY: both descriptors stay open for the duration of the call
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } != 0 {
+        let error = std::io::Error::last_os_error();
+        drop(dst);
+        let _ = std::fs::remove_file(dest);
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_source: &Path, _dest: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "reflinks are only supported on Linux"))
+}
+
+/// Creates a link at `target` through `link`. A file already there (only
+/// reached with `conflict = "overwrite"`) is replaced in one step: the link is
+/// made under a temporary name and renamed over it.
+fn link_over(target: &Path, link: impl FnOnce(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    if target.symlink_metadata().is_err() {
+        return link(target);
+    }
+    let tmp = link_temp(target);
+    link(&tmp).and_then(|_| std::fs::rename(&tmp, target)).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+/// Hidden temporary name next to `path`, for a file that replaces it by a rename.
+pub(crate) fn temp_sibling(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}", file_name, suffix))
+}
+
+/// Where a link replacing `path` is created first; `rarch recover` removes leftovers.
+pub(crate) fn link_temp(path: &Path) -> PathBuf {
+    temp_sibling(path, "rarch-link")
+}
+
+/// True if both paths refer to the same inode.
+#[cfg(unix)]
+pub(crate) fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// One matcher or filter looked at while explaining a rule.
+pub struct MatchStep {
+    pub label: String,
+    /// `None` if the matcher was not evaluated (e.g. the AI is skipped once a cheaper matcher hit)
+    pub result: Option<bool>,
+}
+
+pub struct RuleExplanation {
+    pub name: String,
+    pub steps: Vec<MatchStep>,
+    pub matched: bool,
+}
+
+/// Result of `Engine::explain` for a single file.
+pub struct Explanation {
+    pub detected_mime: Option<String>,
+    pub detected_ext: Option<String>,
+    pub file_ext: Option<String>,
+    pub size: u64,
+    /// Whether a scan of the base directory would pick the file up at all
+    pub in_scan: bool,
+    pub rules: Vec<RuleExplanation>,
+    pub matched_rule: Option<String>,
+    pub target: Option<PathBuf>,
+    /// Outcome of `handle_conflict`: the final path, `None` if skipped, or the error
+    pub final_target: Option<Result<Option<PathBuf>, String>>,
+    /// The file it would be deduplicated against, and how
+    pub duplicate_of: Option<(PathBuf, OpType)>,
+}
+
+/// Everything the matchers need to know about a file, gathered once per file.
+struct FileFacts<'a> {
+    path: &'a Path,
+    metadata: std::fs::Metadata,
+    size: u64,
+    detected_ext: Option<String>,
+    detected_mime: Option<String>,
+    file_ext: Option<String>,
+}
+
+impl<'a> FileFacts<'a> {
+    fn inspect(path: &'a Path) -> Option<Self> {
         let metadata = std::fs::metadata(path).ok()?;
         let size = metadata.len();
 
@@ -386,117 +1562,13 @@ impl Engine {
             .and_then(|s| s.to_str())
             .map(|s| s.to_lowercase());
 
-        for rule in &self.config.rules {
-            let mut matched = false;
-
-            // Check MIME-based matching (Modern/Robust)
-            if let (Some(rule_mime), Some(actual_mime)) = (&rule.mime, &detected_mime) {
-                if rule_mime == actual_mime || (rule_mime.ends_with("/*") && actual_mime.starts_with(&rule_mime[..rule_mime.len() - 1])) {
-                    matched = true;
-                }
-            }
-
-            // Check Preset-based matching (User-friendly)
-            if !matched {
-                if let (Some(rule_type), Some(actual_mime)) = (&rule.r#type, &detected_mime) {
-                    let type_match = match rule_type.as_str() {
-                        "image" => actual_mime.starts_with("image/"),
-                        "video" => actual_mime.starts_with("video/"),
-                        "audio" => actual_mime.starts_with("audio/"),
-                        "document" => {
-                            actual_mime.contains("pdf")
-                                || actual_mime.contains("word")
-                                || actual_mime.contains("text")
-                        }
-                        _ => false,
-                    };
-                    if type_match {
-                        matched = true;
-                    }
-                }
-            }
-
-            // Check Extension-based matching (Classic/Fallback)
-            if !matched {
-                if let Some(rule_exts) = &rule.extensions {
-                    matched = rule_exts.iter().any(|e| {
-                        let e_low = e.to_lowercase();
-                        Some(&e_low) == detected_ext.as_ref() || Some(e_low) == file_ext
-                    });
-                }
-            }
-
-            // Check Regex-based matching (Advanced)
-            if !matched {
-                if let (Some(rule_regex), Some(filename)) = (&rule.regex, path.file_name().and_then(|s| s.to_str())) {
-                    if let Ok(re) = Regex::new(rule_regex) {
-                        if re.is_match(filename) {
-                            matched = true;
-                        }
-                    }
-                }
-            }
-
-            // Check AI-based matching (Experimental/Smart)
-            if !matched {
-                if let (Some(ai_prompt), Some(filename), Some(ai_oracle)) = (&rule.ai_prompt, path.file_name().and_then(|s| s.to_str()), self.ai.as_ref()) {
-                    // Try to read a snippet of content if it's likely text
-                    let content_snippet = if let Ok(mut file) = std::fs::File::open(path) {
-                        let mut buffer = [0; 512];
-                        if let Ok(n) = file.read(&mut buffer) {
-                            if std::str::from_utf8(&buffer[..n]).is_ok() {
-                                Some(String::from_utf8_lossy(&buffer[..n]).to_string())
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-
-                    if ai_oracle.matches_prompt(filename, content_snippet.as_deref(), ai_prompt, reporter.clone()) {
-                        matched = true;
-                    }
-                }
-            }
-
-            if matched {
-                // Apply AND filters (Size, Age)
-                if let Some(min_size) = rule.min_size {
-                    if size < min_size {
-                        continue;
-                    }
-                }
-
-                if let Some(max_age_str) = &rule.max_age {
-                    if let Some(duration) = self.parse_age(max_age_str) {
-                        if let Ok(modified) = metadata.modified() {
-                            let duration_since_mod = Utc::now().signed_duration_since(chrono::DateTime::<Utc>::from(modified));
-                            if duration_since_mod < duration {
-                                continue;
-                            }
-                        }
-                    }
-                }
-                
-                return Some(rule);
-            }
-        }
-        None
-    }
-
-    fn parse_age(&self, s: &str) -> Option<Duration> {
-        let (num_part, unit_part) = s.split_at(s.len() - 1);
-        let num = num_part.parse::<i64>().ok()?;
-        match unit_part.to_lowercase().as_str() {
-            "d" => Some(Duration::days(num)),
-            "w" => Some(Duration::weeks(num)),
-            "m" => Some(Duration::days(num * 30)), // Rough month
-            "y" => Some(Duration::days(num * 365)), // Rough year
-            "h" => Some(Duration::hours(num)),
-            _ => None,
-        }
+        Some(Self {
+            path,
+            metadata,
+            size,
+            detected_ext,
+            detected_mime,
+            file_ext,
+        })
     }
 }
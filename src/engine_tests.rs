@@ -1,7 +1,8 @@
 #[cfg(test)]
 mod tests {
     use crate::engine::Engine;
-    use crate::config::{Config, Rule};
+    use crate::config::{parse_size, Config, Rule};
+    use crate::journal::OpType;
     use std::path::{PathBuf};
     use tempfile::tempdir;
     use std::fs;
@@ -9,7 +10,7 @@ mod tests {
     #[test]
     fn test_resolve_placeholders() {
         let config = Config::default();
-        let engine = Engine::new(config, PathBuf::from("."));
+        let engine = Engine::new(config, PathBuf::from(".")).unwrap();
         let path = PathBuf::from("test.txt");
         
         let rule = Rule {
@@ -50,7 +51,7 @@ mod tests {
             rules: vec![rule],
             ..Default::default()
         };
-        let engine = Engine::new(config, dir.path().to_path_buf());
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
         
         let matched = engine.match_rule::<fn(&str)>(&file_path, None);
         assert!(matched.is_some());
@@ -80,7 +81,7 @@ mod tests {
             rules: vec![rule],
             ..Default::default()
         };
-        let engine = Engine::new(config, dir.path().to_path_buf());
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
         
         let matched = engine.match_rule::<fn(&str)>(&file_path, None);
         assert!(matched.is_some());
@@ -105,10 +106,960 @@ mod tests {
             rules: vec![rule],
             ..Default::default()
         };
-        let engine = Engine::new(config, dir.path().to_path_buf());
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
         
         let matched = engine.match_rule::<fn(&str)>(&file_path, None);
         assert!(matched.is_some());
         assert_eq!(matched.unwrap().name, "doc_rule");
     }
+
+    #[test]
+    fn test_dry_run_depth_and_exclusions() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::create_dir_all(dir.path().join("nested/deeper")).unwrap();
+        fs::create_dir_all(dir.path().join("sorted")).unwrap();
+        fs::create_dir_all(dir.path().join(".cache")).unwrap();
+        fs::write(dir.path().join("top.txt"), "top").unwrap();
+        fs::write(dir.path().join("nested/mid.txt"), "mid").unwrap();
+        fs::write(dir.path().join("nested/deeper/low.txt"), "low").unwrap();
+        fs::write(dir.path().join("sorted/done.txt"), "done").unwrap();
+        fs::write(dir.path().join(".cache/hidden.txt"), "hidden").unwrap();
+
+        let rule = Rule {
+            name: "text".into(),
+            extensions: Some(vec!["txt".into()]),
+            target: "sorted".into(),
+            ..Default::default()
+        };
+        let scanned = |max_depth: Option<usize>, include_hidden: bool| {
+            let config = Config {
+                rules: vec![rule.clone()],
+                max_depth,
+                include_hidden,
+                ..Default::default()
+            };
+            let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+            let mut names: Vec<String> = engine
+                .dry_run(|_, _, _| {})
+                .unwrap()
+                .into_iter()
+                .map(|op| op.from.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(scanned(None, false), vec!["top.txt"]);
+        assert_eq!(scanned(Some(2), false), vec!["mid.txt", "top.txt"]);
+        assert_eq!(scanned(Some(0), false), vec!["low.txt", "mid.txt", "top.txt"]);
+        assert_eq!(scanned(Some(0), true), vec!["hidden.txt", "low.txt", "mid.txt", "top.txt"]);
+    }
+
+    #[test]
+    fn test_dry_run_respects_ignore_files_and_exclude() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::create_dir_all(dir.path().join("project/keep")).unwrap();
+        fs::write(dir.path().join("report.txt"), "report").unwrap();
+        fs::write(dir.path().join("video.txt.part"), "partial").unwrap();
+        fs::write(dir.path().join("notes.lock"), "lock").unwrap();
+        fs::write(dir.path().join("project/main.txt"), "main").unwrap();
+        fs::write(dir.path().join("project/keep/readme.txt"), "readme").unwrap();
+        fs::write(dir.path().join(".rarchignore"), "*.lock\nproject/*\n!project/keep/\n").unwrap();
+        fs::write(dir.path().join("project/keep/.rarchignore"), "readme.txt\n").unwrap();
+        fs::write(dir.path().join("project/keep/other.txt"), "other").unwrap();
+
+        let config = Config {
+            rules: vec![Rule {
+                name: "all".into(),
+                regex: Some(".*".into()),
+                target: "sorted".into(),
+                ..Default::default()
+            }],
+            max_depth: Some(0),
+            exclude: vec!["*.part".into()],
+            ..Default::default()
+        };
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let mut names: Vec<String> = engine
+            .dry_run(|_, _, _| {})
+            .unwrap()
+            .into_iter()
+            .map(|op| op.from.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["other.txt", "report.txt"]);
+
+        let watched = engine.process_single_file::<fn(&str)>(dir.path().join("notes.lock"), None);
+        assert!(watched.unwrap().is_none());
+
+        // Watch mode reloads an ignore file once it changes
+        fs::write(dir.path().join(".rarchignore"), "project/*\n!project/keep/\n").unwrap();
+        assert!(engine.process_single_file::<fn(&str)>(dir.path().join("notes.lock"), None).unwrap().is_none());
+        assert!(engine.reload_ignore_file(&dir.path().join(".rarchignore")));
+        assert!(!engine.reload_ignore_file(&dir.path().join("notes.lock")));
+        let watched = engine.process_single_file::<fn(&str)>(dir.path().join("notes.lock"), None);
+        assert!(watched.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_match_rule_exclusions() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let png = vec![0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+        fs::create_dir_all(dir.path().join("phone")).unwrap();
+        fs::write(dir.path().join("holiday.png"), &png).unwrap();
+        fs::write(dir.path().join("Screenshot_01.png"), &png).unwrap();
+        fs::write(dir.path().join("phone/cat.png"), &png).unwrap();
+        fs::write(dir.path().join("logo.gif"), b"GIF89a").unwrap();
+
+        let rule = Rule {
+            name: "photos".into(),
+            r#type: Some("image".into()),
+            exclude_regex: Some("^Screenshot".into()),
+            exclude_extensions: Some(vec!["GIF".into()]),
+            not_path: Some("phone/**".into()),
+            target: "photos".into(),
+            ..Default::default()
+        };
+        let config = Config {
+            rules: vec![rule],
+            ..Default::default()
+        };
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+
+        assert!(engine.match_rule::<fn(&str)>(&dir.path().join("holiday.png"), None).is_some());
+        assert!(engine.match_rule::<fn(&str)>(&dir.path().join("Screenshot_01.png"), None).is_none());
+        assert!(engine.match_rule::<fn(&str)>(&dir.path().join("phone/cat.png"), None).is_none());
+        assert!(engine.match_rule::<fn(&str)>(&dir.path().join("logo.gif"), None).is_none());
+    }
+
+    #[test]
+    fn test_match_rule_condition_tree() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let pdf = vec![0x25, 0x50, 0x44, 0x46];
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(100 * 24 * 3600);
+        for name in ["invoice_old.pdf", "receipt_old.pdf", "invoice_new.pdf", "invoice_draft_old.pdf"] {
+            fs::write(dir.path().join(name), &pdf).unwrap();
+            if name.contains("old") {
+                let file = fs::File::options().write(true).open(dir.path().join(name)).unwrap();
+                file.set_modified(old).unwrap();
+            }
+        }
+
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            name = "Old Bills"
+            target = "bills"
+            all = [
+                { mime = "application/pdf" },
+                { max_age = "90d" },
+                { any = [{ regex = "invoice" }, { regex = "receipt" }] },
+            ]
+            not = { path = "*draft*" }
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let matches = |name: &str| engine.match_rule::<fn(&str)>(&dir.path().join(name), None).is_some();
+
+        assert!(matches("invoice_old.pdf"));
+        assert!(matches("receipt_old.pdf"));
+        assert!(!matches("invoice_new.pdf"));
+        assert!(!matches("invoice_draft_old.pdf"));
+    }
+
+    #[test]
+    fn test_parse_age_and_size() {
+        use chrono::{Duration, TimeZone, Utc};
+
+        assert_eq!(Engine::parse_age("30d"), Some(Duration::days(30)));
+        assert_eq!(Engine::parse_age("1w3d"), Some(Duration::days(10)));
+        assert_eq!(Engine::parse_age("2h30min"), Some(Duration::minutes(150)));
+        assert_eq!(Engine::parse_age("3x"), None);
+        assert_eq!(Engine::parse_age(""), None);
+        assert_eq!(Engine::parse_age("99999999999999d"), None);
+        assert_eq!(Engine::parse_age("99999999999999999y"), None);
+
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        assert_eq!(Engine::age_cutoff("1d", now), Some(now - Duration::days(1)));
+        assert_eq!(Engine::age_cutoff("99999999d", now), None);
+        assert_eq!(
+            Engine::age_cutoff("2024-01-31T00:00:00Z", now),
+            Some(Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap())
+        );
+        assert!(Engine::age_cutoff("2024-01-31", now).is_some());
+
+        let config = Config {
+            rules: vec![Rule { name: "ancient".into(), older_than: Some("99999999999999d".into()), ..Default::default() }],
+            ..Default::default()
+        };
+        assert!(Engine::new(config, PathBuf::from(".")).is_err());
+
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("10MB"), Some(10_000_000));
+        assert_eq!(parse_size("2GiB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5k"), Some(1536));
+        assert_eq!(parse_size("ten"), None);
+    }
+
+    #[test]
+    fn test_match_rule_size_and_age_ranges() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::write(dir.path().join("small.log"), vec![0u8; 10]).unwrap();
+        fs::write(dir.path().join("medium.log"), vec![0u8; 2000]).unwrap();
+        fs::write(dir.path().join("large.log"), vec![0u8; 5000]).unwrap();
+        let stale = fs::File::options().write(true).open(dir.path().join("large.log")).unwrap();
+        stale
+            .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 24 * 3600))
+            .unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            name = "Medium"
+            extensions = ["log"]
+            min_size = "1KB"
+            max_size = "4KiB"
+            target = "medium"
+
+            [[rules]]
+            name = "Recent"
+            extensions = ["log"]
+            newer_than = "1w"
+            target = "recent"
+
+            [[rules]]
+            name = "Stale"
+            extensions = ["log"]
+            max_age = "1w"
+            target = "stale"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let rule_of = |name: &str| {
+            engine
+                .match_rule::<fn(&str)>(&dir.path().join(name), None)
+                .map(|r| r.name.clone())
+        };
+
+        assert_eq!(rule_of("medium.log").as_deref(), Some("Medium"));
+        assert_eq!(rule_of("small.log").as_deref(), Some("Recent"));
+        assert_eq!(rule_of("large.log").as_deref(), Some("Stale"));
+    }
+
+    #[test]
+    fn test_engine_rejects_invalid_patterns() {
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            name = "Broken"
+            target = "broken"
+            any = [{ extensions = ["txt"] }, { regex = "([a-z" }]
+            "#,
+        )
+        .unwrap();
+        let err = Engine::new(config, PathBuf::from(".")).err().expect("invalid regex must be rejected");
+        let message = format!("{:#}", err);
+        assert!(message.contains("rule 'Broken'"), "{}", message);
+        assert!(message.contains("([a-z"), "{}", message);
+    }
+
+    #[test]
+    fn test_engine_rejects_unusable_similarity_settings() {
+        let config: Config = toml::from_str(
+            r#"
+            similarity_threshold = 65
+
+            [[rules]]
+            name = "Photos"
+            type = "image"
+            target = "photos"
+            "#,
+        )
+        .unwrap();
+        assert!(Engine::new(config, PathBuf::from(".")).is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            name = "Lookalikes"
+            type = "image"
+            similar_to_existing = false
+            target = "photos"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Engine::new(config, PathBuf::from(".")).is_ok(), cfg!(feature = "phash"));
+    }
+
+    #[test]
+    fn test_explain_reports_every_rule() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::write(dir.path().join("report.pdf"), vec![0x25, 0x50, 0x44, 0x46]).unwrap();
+        fs::write(dir.path().join("copy.pdf"), vec![0x25, 0x50, 0x44, 0x46]).unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Large"
+            extensions = ["pdf"]
+            min_size = "1MB"
+            target = "large"
+
+            [[rules]]
+            name = "Docs"
+            mime = "application/pdf"
+            target = "docs"
+
+            [[rules]]
+            name = "Any"
+            regex = ".*"
+            target = "misc"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let explanation = engine.explain(&dir.path().join("report.pdf")).unwrap();
+
+        assert_eq!(explanation.detected_mime.as_deref(), Some("application/pdf"));
+        assert!(explanation.in_scan);
+        let verdicts: Vec<bool> = explanation.rules.iter().map(|r| r.matched).collect();
+        assert_eq!(verdicts, vec![false, true, true]);
+        assert_eq!(explanation.rules[0].steps.len(), 2);
+        assert_eq!(explanation.rules[0].steps[1].result, Some(false));
+        assert_eq!(explanation.matched_rule.as_deref(), Some("Docs"));
+        assert_eq!(explanation.target, Some(dir.path().join("docs/report.pdf")));
+        assert_eq!(explanation.final_target, Some(Ok(Some(dir.path().join("docs/report.pdf")))));
+        // The first path in sort order is kept as the original
+        let original = dir.path().join("docs/copy.pdf");
+        assert_eq!(explanation.duplicate_of, Some((original.clone(), OpType::HardLink(original))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_copy_and_link_actions() {
+
+        let dir = tempdir().expect("Failed to create temp dir");
+        fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+        fs::write(dir.path().join("b.md"), "beta").unwrap();
+        fs::write(dir.path().join("c.log"), "gamma").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Copies"
+            extensions = ["txt"]
+            action = "copy"
+            target = "copies"
+
+            [[rules]]
+            name = "Symlinks"
+            extensions = ["md"]
+            action = "symlink"
+            target = "links"
+
+            [[rules]]
+            name = "Hardlinks"
+            extensions = ["log"]
+            action = "hardlink"
+            target = "hard"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+
+        let mut kinds: Vec<OpType> = journal.operations.iter().map(|op| op.op_type.clone()).collect();
+        kinds.sort_by_key(|k| format!("{:?}", k));
+        assert_eq!(kinds, vec![OpType::Copy, OpType::Link, OpType::Symlink]);
+
+        // Originals stay in place
+        for name in ["a.txt", "b.md", "c.log"] {
+            assert!(dir.path().join(name).is_file());
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("copies/a.txt")).unwrap(), "alpha");
+        assert!(dir.path().join("links/b.md").is_symlink());
+        assert_eq!(fs::read_to_string(dir.path().join("links/b.md")).unwrap(), "beta");
+        assert!(crate::engine::same_file(&dir.path().join("c.log"), &dir.path().join("hard/c.log")));
+
+        // Running again must not create "(1)" duplicates
+        let again = engine.execute(None, |_, _, _| {}).unwrap();
+        assert!(again.operations.is_empty());
+        assert!(!dir.path().join("copies/a (1).txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_actions_overwrite_existing_targets() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("b.md"), "beta").unwrap();
+        fs::write(dir.path().join("c.log"), "gamma").unwrap();
+        fs::create_dir_all(dir.path().join("links")).unwrap();
+        fs::write(dir.path().join("links/b.md"), "old").unwrap();
+        fs::create_dir_all(dir.path().join("hard")).unwrap();
+        fs::write(dir.path().join("hard/c.log"), "old").unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Symlinks"
+            extensions = ["md"]
+            action = "symlink"
+            target = "links"
+            conflict = "overwrite"
+
+            [[rules]]
+            name = "Hardlinks"
+            extensions = ["log"]
+            action = "hardlink"
+            target = "hard"
+            conflict = "overwrite"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        assert_eq!(journal.operations.len(), 2);
+
+        assert!(dir.path().join("links/b.md").is_symlink());
+        assert_eq!(fs::read_to_string(dir.path().join("links/b.md")).unwrap(), "beta");
+        assert!(crate::engine::same_file(&dir.path().join("c.log"), &dir.path().join("hard/c.log")));
+        assert!(!dir.path().join("links/.b.md.rarch-link").exists());
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_execute_archive_action_appends_and_restores() {
+        use crate::archive;
+        let dir = tempdir().unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Logs"
+            extensions = ["log"]
+            action = "archive"
+            target = "old/logs.tar.zst"
+
+            [[rules]]
+            name = "Notes"
+            extensions = ["txt"]
+            action = "archive"
+            target = "old/notes.zip"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+
+        for round in ["first", "second"] {
+            fs::write(dir.path().join("app.log"), format!("{} log", round)).unwrap();
+            fs::write(dir.path().join("todo.txt"), format!("{} note", round)).unwrap();
+            let journal = engine.execute(None, |_, _, _| {}).unwrap();
+            assert_eq!(journal.operations.len(), 2);
+            assert!(!dir.path().join("app.log").exists());
+            assert!(!dir.path().join("todo.txt").exists());
+        }
+
+        // Appending keeps earlier members and never reuses a name
+        let tar = dir.path().join("old/logs.tar.zst");
+        let zip = dir.path().join("old/notes.zip");
+        assert_eq!(archive::members(&tar).unwrap(), vec!["app.log", "app (1).log"]);
+        assert_eq!(archive::members(&zip).unwrap(), vec!["todo.txt", "todo (1).txt"]);
+
+        let restored = dir.path().join("restored.log");
+        archive::extract_member(&tar, "app.log", &restored).unwrap();
+        assert_eq!(fs::read_to_string(&restored).unwrap(), "first log");
+        archive::extract_member(&zip, "todo (1).txt", &restored).unwrap();
+        assert_eq!(fs::read_to_string(&restored).unwrap(), "second note");
+
+        // A failed append leaves both kinds of archive exactly as they were
+        let unreadable = dir.path().join("unreadable");
+        fs::create_dir(&unreadable).unwrap();
+        for archive_path in [&tar, &zip] {
+            let before = fs::read(archive_path).unwrap();
+            let cache = archive::MemberCache::default();
+            assert!(archive::append(archive_path, &unreadable, &cache).is_err());
+            assert_eq!(fs::read(archive_path).unwrap(), before);
+            assert!(!cache.contains(archive_path, "unreadable").unwrap());
+        }
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_append_to_tar_written_by_another_tool() {
+        use crate::archive;
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("old.log"), "old").unwrap();
+        fs::write(dir.path().join("new.log"), "new").unwrap();
+
+        // tar::Builder ends the archive with the end-of-archive blocks
+        let tar = dir.path().join("logs.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&tar).unwrap());
+        builder.append_path_with_name(dir.path().join("old.log"), "old.log").unwrap();
+        builder.into_inner().unwrap();
+        archive::append(&tar, &dir.path().join("new.log"), &archive::MemberCache::default()).unwrap();
+
+        // A reader that stops at the first end marker still sees the new member
+        let mut reader = tar::Archive::new(fs::File::open(&tar).unwrap());
+        let names: Vec<_> = reader
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["old.log", "new.log"]);
+
+        // A compressed one cannot be cut, so it is left alone
+        let gz = dir.path().join("logs.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(fs::File::create(&gz).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        builder.append_path_with_name(dir.path().join("old.log"), "old.log").unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+        let before = fs::read(&gz).unwrap();
+        let err = archive::append(&gz, &dir.path().join("new.log"), &archive::MemberCache::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("not written by rarch"));
+        assert_eq!(fs::read(&gz).unwrap(), before);
+    }
+
+    #[cfg(feature = "archive")]
+    fn write_zip(path: &std::path::Path, entries: &[(&str, &[u8])]) {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_execute_extract_action_journals_each_file() {
+        let dir = tempdir().unwrap();
+        write_zip(&dir.path().join("photos.zip"), &[("a.jpg", b"a"), ("nested/b.jpg", b"b")]);
+        fs::create_dir_all(dir.path().join("unpacked/photos")).unwrap();
+        fs::write(dir.path().join("unpacked/photos/a.jpg"), "existing").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Zips"
+            extensions = ["zip"]
+            action = "extract"
+            target = "unpacked/${name}"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+
+        let mut extracted: Vec<_> = journal.operations.iter().map(|op| op.to.clone()).collect();
+        extracted.sort();
+        let out = dir.path().join("unpacked/photos").canonicalize().unwrap();
+        // The directory the extraction created is journaled too, so undo can remove it
+        assert_eq!(extracted, vec![out.join("a (1).jpg"), out.join("nested"), out.join("nested/b.jpg")]);
+        assert!(journal.operations.iter().all(|op| op.op_type == OpType::Extract));
+        assert_eq!(fs::read_to_string(out.join("a.jpg")).unwrap(), "existing");
+        assert!(dir.path().join("photos.zip").exists());
+
+        // Replacing existing files would lose them, so it is refused up front
+        let config: Config = toml::from_str(
+            r#"
+            [[rules]]
+            name = "Zips"
+            extensions = ["zip"]
+            action = "extract"
+            conflict = "overwrite"
+            target = "unpacked/${name}"
+            "#,
+        )
+        .unwrap();
+        assert!(Engine::new(config, dir.path().to_path_buf()).is_err());
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_extract_rejects_zip_slip_and_bombs() {
+        use crate::archive::{self, ExtractLimits};
+        use crate::config::ConflictStrategy;
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("out");
+
+        let slip = dir.path().join("slip.zip");
+        write_zip(&slip, &[("ok.txt", b"fine"), ("../evil.txt", b"gotcha")]);
+        let err = archive::extract(&slip, &dest, &ConflictStrategy::Rename, &ExtractLimits::default(), &mut |_| Ok(())).unwrap_err();
+        assert!(format!("{:#}", err).contains("unsafe entry path"));
+        assert!(!dir.path().join("evil.txt").exists());
+        // Partial results are rolled back
+        assert!(!dest.exists());
+
+        let bomb = dir.path().join("bomb.zip");
+        write_zip(&bomb, &[("zeros.bin", &vec![0u8; 32 * 1024 * 1024])]);
+        let err = archive::extract(&bomb, &dest, &ConflictStrategy::Rename, &ExtractLimits::default(), &mut |_| Ok(())).unwrap_err();
+        assert!(format!("{:#}", err).contains("compression ratio"));
+        assert!(!dest.exists());
+
+        let limits = ExtractLimits { max_bytes: 1024, ..ExtractLimits::default() };
+        let err = archive::extract(&bomb, &dest, &ConflictStrategy::Rename, &limits, &mut |_| Ok(())).unwrap_err();
+        assert!(format!("{:#}", err).contains("more than 1024 bytes"));
+    }
+
+    #[test]
+    fn test_find_duplicates_stages() {
+        use crate::dedup::find_duplicates;
+        let dir = tempdir().unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+            path
+        };
+
+        // Large files sharing both edges but differing in the middle
+        let mut big = vec![7u8; 300 * 1024];
+        let big_a = write("big_a.bin", &big);
+        let big_b = write("big_b.bin", &big);
+        big[150 * 1024] = 8;
+        let big_c = write("big_c.bin", &big);
+        // Same size, different content
+        let small_a = write("small_a.txt", b"aaaa");
+        let small_b = write("small_b.txt", b"bbbb");
+        let small_c = write("small_c.txt", b"aaaa");
+        // Empty files are never grouped
+        let empty_a = write("empty_a", b"");
+        let empty_b = write("empty_b", b"");
+
+        let files = vec![small_c.clone(), big_c, empty_a, big_b.clone(), small_b, small_a.clone(), big_a.clone(), empty_b];
+        assert_eq!(find_duplicates(&files, crate::dedup::hash_file), vec![vec![big_a, big_b], vec![small_a, small_c]]);
+    }
+
+    #[test]
+    fn test_dry_run_dedup_keeps_first_path_as_original() {
+        let dir = tempdir().unwrap();
+        for name in ["c.txt", "a.txt", "b.txt"] {
+            fs::write(dir.path().join(name), "same").unwrap();
+        }
+        fs::write(dir.path().join("d.txt"), "diff").unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Text"
+            extensions = ["txt"]
+            target = "texts"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let ops = engine.dry_run(|_, _, _| {}).unwrap();
+
+        let original = dir.path().join("texts/a.txt");
+        let kinds: Vec<(String, OpType)> = ops
+            .iter()
+            .map(|op| (op.from.file_name().unwrap().to_string_lossy().to_string(), op.op_type.clone()))
+            .collect();
+        // Moves first, then the links that depend on them
+        assert_eq!(kinds.len(), 4);
+        assert!(kinds[..2].iter().all(|(_, kind)| *kind == OpType::Move));
+        let mut links: Vec<_> = kinds[2..].to_vec();
+        links.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            links,
+            vec![
+                ("b.txt".to_string(), OpType::HardLink(original.clone())),
+                ("c.txt".to_string(), OpType::HardLink(original)),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dedup_modes() {
+        let config_for = |mode: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+                hash_cache = false
+                dedup = "{}"
+
+                [[rules]]
+                name = "Text"
+                extensions = ["txt"]
+                target = "texts"
+                "#,
+                mode
+            ))
+            .unwrap()
+        };
+        let setup = || {
+            let dir = tempdir().unwrap();
+            fs::write(dir.path().join("a.txt"), "same").unwrap();
+            fs::write(dir.path().join("b.txt"), "same").unwrap();
+            dir
+        };
+
+        // Off and report leave duplicates to be moved like any other file
+        for mode in ["off", "report"] {
+            let dir = setup();
+            let engine = Engine::new(config_for(mode), dir.path().to_path_buf()).unwrap();
+            let ops = engine.dry_run(|_, _, _| {}).unwrap();
+            assert!(ops.iter().all(|op| op.op_type == OpType::Move));
+            let groups = engine.duplicate_groups(&ops);
+            assert_eq!(groups, vec![vec![dir.path().join("a.txt"), dir.path().join("b.txt")]]);
+        }
+
+        // Report mode only plans
+        let dir = setup();
+        let engine = Engine::new(config_for("report"), dir.path().to_path_buf()).unwrap();
+        assert!(engine.execute(None, |_, _, _| {}).is_err());
+        assert!(dir.path().join("a.txt").is_file() && dir.path().join("b.txt").is_file());
+        assert!(!dir.path().join("texts").exists());
+
+        let dir = setup();
+        let engine = Engine::new(config_for("symlink"), dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let original = dir.path().join("texts/a.txt");
+        assert_eq!(journal.operations[1].op_type, OpType::SoftLink(original.clone()));
+        assert_eq!(fs::read_link(dir.path().join("texts/b.txt")).unwrap(), original.canonicalize().unwrap());
+        assert!(!dir.path().join("b.txt").exists());
+
+        let dir = setup();
+        let engine = Engine::new(config_for("delete"), dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let original = dir.path().join("texts/a.txt");
+        assert_eq!(journal.operations[1].op_type, OpType::Deleted(original.clone()));
+        assert_eq!(journal.operations[1].to, original);
+        assert!(!dir.path().join("b.txt").exists());
+        assert!(!dir.path().join("texts/b.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dedup_verifies_duplicates_before_removing_them() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("texts")).unwrap();
+        fs::write(dir.path().join("texts/a.txt"), "older").unwrap();
+        fs::write(dir.path().join("a.txt"), "same").unwrap();
+        fs::write(dir.path().join("b.txt"), "same").unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Text"
+            extensions = ["txt"]
+            target = "texts"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+
+        // A duplicate modified after the analysis is kept
+        let ops = engine.dry_run(|_, _, _| {}).unwrap();
+        let duplicate = ops.iter().find(|op| op.op_type.duplicate_of().is_some()).unwrap();
+        fs::write(&duplicate.from, "edit").unwrap();
+        let err = engine.apply(duplicate, &dir.path().join("texts/b.txt")).unwrap_err();
+        assert!(format!("{:#}", err).contains("keeping it"));
+        assert!(duplicate.from.is_file());
+        fs::write(&duplicate.from, "same").unwrap();
+
+        // The link follows the original to its renamed target, not the older file in its place
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let renamed = dir.path().join("texts/a (1).txt");
+        assert!(journal.operations.iter().any(|op| op.op_type == OpType::HardLink(renamed.clone())));
+        assert!(crate::engine::same_file(&renamed, &dir.path().join("texts/b.txt")));
+        assert_eq!(fs::read_to_string(dir.path().join("texts/a.txt")).unwrap(), "older");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dedup_against_existing_targets() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("pics/2024")).unwrap();
+        fs::write(dir.path().join("pics/2024/existing.jpg"), "photo").unwrap();
+        fs::write(dir.path().join("new.jpg"), "photo").unwrap();
+        fs::write(dir.path().join("other.jpg"), "other").unwrap();
+        // Same content, but its rule sends it elsewhere
+        fs::create_dir_all(dir.path().join("notes")).unwrap();
+        fs::write(dir.path().join("new.txt"), "photo").unwrap();
+
+        // A relative index path is taken relative to the configuration file
+        let config_path = dir.path().join("rarch.toml");
+        let config_for = |mode: &str| -> Config {
+            let source = format!(
+                r#"
+                hash_cache = false
+                dedup_existing = "{}"
+                dedup_index = "index.json"
+
+                [[rules]]
+                name = "Photos"
+                extensions = ["jpg"]
+                target = "pics/2024"
+
+                [[rules]]
+                name = "Notes"
+                extensions = ["txt"]
+                target = "notes"
+                "#,
+                mode
+            );
+            fs::write(&config_path, source).unwrap();
+            Config::from_file(config_path.clone()).unwrap()
+        };
+        assert_eq!(config_for("skip").dedup_index, Some(dir.path().join("index.json")));
+        let existing = dir.path().join("pics/2024/existing.jpg").canonicalize().unwrap();
+
+        // Skip mode only reports the twin
+        let engine = Engine::new(config_for("skip"), dir.path().to_path_buf()).unwrap();
+        let ops = engine.dry_run(|_, _, _| {}).unwrap();
+        let twin = ops.iter().find(|op| op.from.ends_with("new.jpg")).unwrap();
+        assert_eq!(twin.op_type, OpType::AlreadyPresent);
+        assert_eq!(twin.to, existing);
+        assert!(dir.path().join("index.json").is_file());
+        let note = ops.iter().find(|op| op.from.ends_with("new.txt")).unwrap();
+        assert_eq!(note.op_type, OpType::Move);
+        fs::remove_file(dir.path().join("new.txt")).unwrap();
+
+        // Hardlink mode keeps the file in place but shares the data
+        let engine = Engine::new(config_for("hardlink"), dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let mut kinds: Vec<OpType> = journal.operations.iter().map(|op| op.op_type.clone()).collect();
+        kinds.sort_by_key(|k| format!("{:?}", k));
+        assert_eq!(kinds, vec![OpType::Move, OpType::TwinLink]);
+        assert!(crate::engine::same_file(&dir.path().join("new.jpg"), &existing));
+        assert!(!dir.path().join("pics/2024/new.jpg").exists());
+        assert!(dir.path().join("pics/2024/other.jpg").is_file());
+
+        // Once linked, the file is reported as present rather than linked again
+        let ops = engine.dry_run(|_, _, _| {}).unwrap();
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].op_type, OpType::AlreadyPresent);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_hash_cache_reuses_unchanged_files() {
+        use crate::cache::HashCache;
+
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("hashes.json");
+        let file = dir.path().join("a.bin");
+        fs::write(&file, b"first").unwrap();
+
+        let cache = HashCache::load(cache_path.clone());
+        let first = cache.hash(&file).unwrap();
+        assert_eq!(first, crate::dedup::hash_file(&file).unwrap());
+        cache.save().unwrap();
+
+        // A reloaded cache answers from disk
+        let cache = HashCache::load(cache_path.clone());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.hash(&file).unwrap(), first);
+
+        // A changed size invalidates the entry
+        fs::write(&file, b"second!").unwrap();
+        assert_ne!(cache.hash(&file).unwrap(), first);
+        assert_eq!(cache.len(), 1);
+
+        let other = dir.path().join("b.bin");
+        fs::write(&other, b"other").unwrap();
+        cache.hash(&other).unwrap();
+        assert_eq!(cache.prune(chrono::Duration::days(1), Some(1)).unwrap(), 1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.prune(chrono::Duration::days(1), None).unwrap(), 0);
+
+        // An age reaching past the range of dates is an error, not a panic
+        let max_age = Engine::parse_age("300000y").unwrap();
+        assert!(cache.prune(max_age, None).is_err());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_duplicates_and_pick_keeper() {
+        use crate::dupes::{pick_keeper, Keep};
+
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("deep/er")).unwrap();
+        fs::create_dir(dir.path().join("keep")).unwrap();
+        let paths = [
+            dir.path().join("deep/er/photo.jpg"),
+            dir.path().join("keep/photo-copy.jpg"),
+            dir.path().join("p.jpg"),
+        ];
+        for path in &paths {
+            fs::write(path, "pixels").unwrap();
+        }
+        // A hard link is not a duplicate worth reporting
+        fs::hard_link(&paths[2], dir.path().join("q-link.jpg")).unwrap();
+
+        let config = Config { max_depth: Some(0), ..Default::default() };
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let groups = engine.scan_duplicates();
+        assert_eq!(groups, vec![paths.to_vec()]);
+
+        let group = &groups[0];
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        fs::File::options().write(true).open(&paths[1]).unwrap().set_modified(old).unwrap();
+        assert_eq!(pick_keeper(group, Keep::Shortest, None), 2);
+        assert_eq!(pick_keeper(group, Keep::Oldest, None), 1);
+        let preferred = dir.path().join("deep").canonicalize().unwrap();
+        assert_eq!(pick_keeper(group, Keep::Oldest, Some(&preferred)), 0);
+    }
+
+    #[cfg(feature = "phash")]
+    #[test]
+    fn test_similar_to_existing_routes_resized_copies() {
+        use crate::phash::{dhash, distance};
+
+        let picture = |width: u32, height: u32, invert: bool| {
+            image::GrayImage::from_fn(width, height, |x, y| {
+                let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+                let value = 128.0 + 100.0 * (u * std::f64::consts::TAU * 2.0).sin() * (v * std::f64::consts::TAU).cos();
+                image::Luma([if invert { 255 - value as u8 } else { value as u8 }])
+            })
+        };
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("photos")).unwrap();
+        picture(640, 480, false).save(dir.path().join("photos/shot.png")).unwrap();
+        picture(160, 120, false).save(dir.path().join("shot-small.jpg")).unwrap();
+        picture(640, 480, true).save(dir.path().join("other.png")).unwrap();
+
+        let original = dhash(&dir.path().join("photos/shot.png")).unwrap();
+        assert!(distance(original, dhash(&dir.path().join("shot-small.jpg")).unwrap()) <= 4);
+        assert!(distance(original, dhash(&dir.path().join("other.png")).unwrap()) > 32);
+
+        let mut config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Near duplicates"
+            type = "image"
+            similar_to_existing = true
+            target = "review"
+
+            [[rules]]
+            name = "Photos"
+            type = "image"
+            target = "photos"
+            "#,
+        )
+        .unwrap();
+        config.dedup_index = Some(dir.path().join("index.json"));
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let ops = engine.dry_run(|_, _, _| {}).unwrap();
+        let rule_of = |name: &str| ops.iter().find(|op| op.from.ends_with(name)).unwrap().rule_name.clone().unwrap();
+        assert_eq!(rule_of("shot-small.jpg"), "Near duplicates");
+        assert_eq!(rule_of("other.png"), "Photos");
+    }
 }
@@ -0,0 +1,173 @@
+use crate::cache::mtime_ns;
+use crate::phash;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    size: u64,
+    mtime_ns: i64,
+    /// Computed on demand, only for sizes that an incoming file shares
+    hash: Option<String>,
+    /// Perceptual hash, computed on demand for images
+    #[serde(default)]
+    phash: Option<u64>,
+    /// Set once `phash` was attempted, so non-images are not looked at again
+    #[serde(default)]
+    phash_done: bool,
+}
+
+/// Persistent index of the files already living in the target directories,
+/// used to recognize incoming files whose content is already organized.
+///
+/// Refreshing only stats files; hashes are computed lazily and kept for as
+/// long as the size and modification time of a file stay the same.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TargetIndex {
+    files: BTreeMap<PathBuf, IndexEntry>,
+}
+
+impl TargetIndex {
+    /// The index file in the cache directory `dir`.
+    pub fn path_in(dir: &Path) -> PathBuf {
+        dir.join("target_index.json")
+    }
+
+    /// Loads the index, starting over if it is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Brings the entries below `roots` in line with the file system.
+    pub fn refresh(&mut self, roots: &[PathBuf]) {
+        for root in roots {
+            let mut seen = HashSet::new();
+            for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                let path = entry.into_path();
+                let mtime_ns = mtime_ns(&metadata);
+                let unchanged = self
+                    .files
+                    .get(&path)
+                    .is_some_and(|e| e.size == metadata.len() && e.mtime_ns == mtime_ns);
+                if !unchanged {
+                    let entry = IndexEntry { size: metadata.len(), mtime_ns, hash: None, phash: None, phash_done: false };
+                    self.files.insert(path.clone(), entry);
+                }
+                seen.insert(path);
+            }
+            self.files.retain(|path, _| !path.starts_with(root) || seen.contains(path));
+        }
+    }
+
+    /// Forgets files that no longer exist. Returns how many were removed.
+    pub fn prune(&mut self) -> usize {
+        let before = self.files.len();
+        self.files.retain(|path, _| path.is_file());
+        before - self.files.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Perceptual hashes of every indexed image, computing the missing ones.
+    pub fn image_hashes(&mut self) -> Vec<(PathBuf, u64)> {
+        if !cfg!(feature = "phash") {
+            return Vec::new(); // Would mark every image as done without a hash
+        }
+        let missing: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, e)| !e.phash_done)
+            .map(|(path, _)| path.clone())
+            .collect();
+        let computed: Vec<(PathBuf, Option<u64>)> = missing
+            .into_par_iter()
+            .map(|path| {
+                let phash = if phash::is_image(&path) { phash::dhash(&path).ok() } else { None };
+                (path, phash)
+            })
+            .collect();
+        for (path, phash) in computed {
+            if let Some(entry) = self.files.get_mut(&path) {
+                entry.phash = phash;
+                entry.phash_done = true;
+            }
+        }
+        self.files
+            .iter()
+            .filter_map(|(path, e)| Some((path.clone(), e.phash?)))
+            .collect()
+    }
+
+    /// Maps each incoming file to an indexed file below `root` with identical content.
+    pub fn find_twins<H>(&mut self, files: &[PathBuf], root: &Path, hash: H) -> HashMap<PathBuf, PathBuf>
+    where
+        H: Fn(&Path) -> std::io::Result<String> + Sync,
+    {
+        let incoming: Vec<(PathBuf, u64)> = files
+            .iter()
+            .filter_map(|path| Some((path.clone(), std::fs::metadata(path).ok()?.len())))
+            .filter(|(_, size)| *size > 0)
+            .collect();
+        let sizes: HashSet<u64> = incoming.iter().map(|(_, size)| *size).collect();
+
+        // Hash indexed files only where an incoming file could match
+        let missing: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(path, e)| e.hash.is_none() && sizes.contains(&e.size) && path.starts_with(root))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let hashed: Vec<(PathBuf, String)> = missing
+            .into_par_iter()
+            .filter_map(|path| hash(&path).ok().map(|hash| (path, hash)))
+            .collect();
+        for (path, hash) in hashed {
+            if let Some(entry) = self.files.get_mut(&path) {
+                entry.hash = Some(hash);
+            }
+        }
+
+        // First path wins, as the map is sorted
+        let mut by_content: HashMap<(u64, &str), &PathBuf> = HashMap::new();
+        for (path, entry) in self.files.range(root.to_path_buf()..).take_while(|(path, _)| path.starts_with(root)) {
+            if let Some(hash) = &entry.hash {
+                by_content.entry((entry.size, hash.as_str())).or_insert(path);
+            }
+        }
+        let indexed_sizes: HashSet<u64> = by_content.keys().map(|(size, _)| *size).collect();
+
+        incoming
+            .into_par_iter()
+            .filter(|(_, size)| indexed_sizes.contains(size))
+            .filter_map(|(path, size)| {
+                let content = hash(&path).ok()?;
+                let twin = by_content.get(&(size, content.as_str()))?;
+                Some((path, (*twin).clone()))
+            })
+            .collect()
+    }
+}
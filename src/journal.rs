@@ -1,8 +1,14 @@
+use crate::cache::mtime_ns;
+use crate::dedup;
+use anyhow::Context;
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JournalEntry {
@@ -21,7 +27,98 @@ pub struct Operation {
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum OpType {
     Move,
+    /// Duplicate removed from `from`; `to` is a hard link to the contained original
     HardLink(PathBuf),
+    /// Duplicate removed from `from`; `to` is a copy-on-write clone of the contained original
+    Reflink(PathBuf),
+    /// Duplicate removed from `from`; `to` is a symbolic link to the contained original
+    SoftLink(PathBuf),
+    /// Duplicate removed from `from` without replacement; `to` is the contained original
+    Deleted(PathBuf),
+    /// `to` is a copy of `from`; the source is left in place
+    Copy,
+    /// `to` is a symbolic link pointing at `from`
+    Symlink,
+    /// `to` is a hard link to `from`; the source is left in place
+    Link,
+    /// `from` was moved into the trash at `to`; holds the path of its `.trashinfo` record
+    Trash(PathBuf),
+    /// `from` was stored in `archive` as `member` and then removed
+    Archive { archive: PathBuf, member: String },
+    /// `to` was unpacked from the archive `from`, which is left in place
+    Extract,
+    /// `from` was replaced by a hard link to `to`, an identical file already in a target directory
+    TwinLink,
+    /// `from` was left alone because `to` already holds the same content; never journaled
+    AlreadyPresent,
+}
+
+impl OpType {
+    /// The original a deduplicated file was replaced by, if this is a dedup operation.
+    pub fn duplicate_of(&self) -> Option<&Path> {
+        match self {
+            OpType::HardLink(original) | OpType::Reflink(original) | OpType::SoftLink(original) | OpType::Deleted(original) => {
+                Some(original)
+            }
+            _ => None,
+        }
+    }
+
+    /// Short description for tables.
+    pub fn label(&self) -> &'static str {
+        match self {
+            OpType::Move => "Move",
+            OpType::Copy => "Copy",
+            OpType::Symlink => "Symlink",
+            OpType::Link => "Hard link",
+            OpType::Trash(_) => "Trash",
+            OpType::Archive { .. } => "Archive",
+            OpType::Extract => "Extract",
+            OpType::TwinLink => "Link to existing",
+            OpType::AlreadyPresent => "Already present",
+            OpType::HardLink(_) => "Deduplicate",
+            OpType::Reflink(_) => "Deduplicate (reflink)",
+            OpType::SoftLink(_) => "Deduplicate (symlink)",
+            OpType::Deleted(_) => "Delete duplicate",
+        }
+    }
+}
+
+impl Operation {
+    /// The file undo puts back or removes, for operations that leave one of their own.
+    pub fn result_path(&self) -> Option<&Path> {
+        match self.op_type {
+            OpType::Move
+            | OpType::HardLink(_)
+            | OpType::Reflink(_)
+            | OpType::Copy
+            | OpType::Link
+            | OpType::Extract
+            | OpType::Trash(_) => Some(&self.to),
+            OpType::TwinLink => Some(&self.from),
+            OpType::SoftLink(_) | OpType::Deleted(_) | OpType::Symlink | OpType::Archive { .. } | OpType::AlreadyPresent => None,
+        }
+    }
+
+    /// The operation with every path made absolute, so it can be undone from any directory.
+    fn absolute(&self) -> Self {
+        let abs = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let op_type = match &self.op_type {
+            OpType::HardLink(original) => OpType::HardLink(abs(original)),
+            OpType::Reflink(original) => OpType::Reflink(abs(original)),
+            OpType::SoftLink(original) => OpType::SoftLink(abs(original)),
+            OpType::Deleted(original) => OpType::Deleted(abs(original)),
+            OpType::Trash(info_path) => OpType::Trash(abs(info_path)),
+            OpType::Archive { archive, member } => OpType::Archive { archive: abs(archive), member: member.clone() },
+            other => other.clone(),
+        };
+        Self {
+            from: abs(&self.from),
+            to: abs(&self.to),
+            op_type,
+            rule_name: self.rule_name.clone(),
+        }
+    }
 }
 
 impl JournalEntry {
@@ -32,25 +129,7 @@ impl JournalEntry {
         }
     }
 
-    /// Appends a single operation to a journal file in a crash-safe way (JSON Lines)
-    pub fn append_to_file(path: &Path, op: &Operation) -> anyhow::Result<()> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
-        
-        let json = serde_json::to_string(op)?;
-        writeln!(file, "{}", json)?;
-        Ok(())
-    }
-
-    /// Regular save for the whole structure
-    pub fn save(&self, path: PathBuf) -> anyhow::Result<()> {
-        let content = serde_json::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
-        Ok(())
-    }
-
+    /// Reads a journal written by older versions (`rarch_journal.json`).
     pub fn load(path: PathBuf) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(&path)?;
         // Try parsing as full JournalEntry (legacy) or JSON Lines
@@ -70,3 +149,372 @@ impl JournalEntry {
         })
     }
 }
+
+/// What started a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub started: DateTime<Local>,
+    /// Subcommand that recorded it (`run`, `watch`, `dupes`, `ui`, `undo`, `redo`)
+    pub command: String,
+    /// Directory that was organized
+    pub path: PathBuf,
+    /// For an undo, the session it rolled back; its operations are the ones reverted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<String>,
+}
+
+/// One line of a session log.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    /// Always the first line
+    Session(SessionInfo),
+    /// Written (and synced) before an operation touches any file
+    Intent { id: u64, op: Operation },
+    /// The pending operation with that ID is about to create this file or
+    /// directory; written by extractions, whose files are not known in advance
+    Created { id: u64, path: PathBuf },
+    /// The operation with that ID is done; holds it as performed
+    Commit {
+        id: u64,
+        ops: Vec<Operation>,
+        /// One per operation, taken right after it was performed
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fingerprints: Vec<Option<Fingerprint>>,
+    },
+    /// The operation with that ID failed or was rolled back
+    Abort(u64),
+    /// The committed operation at that position was undone on its own
+    Reverted(usize),
+    /// The session was rolled back
+    Undone(DateTime<Local>),
+}
+
+impl Record {
+    /// A commit record, with the fingerprints of the results if `fingerprint` is set.
+    fn commit(id: u64, ops: Vec<Operation>, fingerprint: bool) -> Self {
+        let fingerprints = match fingerprint {
+            true => ops.iter().map(Fingerprint::of_result).collect(),
+            false => Vec::new(),
+        };
+        Record::Commit { id, ops, fingerprints }
+    }
+}
+
+/// Size, modification time and, where taken, content hash of a file, to
+/// tell later whether it changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub mtime: i64,
+    /// Hex SHA-256 of the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl Fingerprint {
+    /// Size and modification time of `path`, without reading it.
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self { size: metadata.len(), mtime: mtime_ns(&metadata), hash: None })
+    }
+
+    /// Fingerprint of what `op` produced. Results that undo removes (copies,
+    /// links and extracted files) also get their content hash, so an edit
+    /// that keeps the size and modification time is still noticed.
+    fn of_result(op: &Operation) -> Option<Self> {
+        let path = op.result_path()?;
+        let mut fingerprint = Self::of(path)?;
+        if matches!(op.op_type, OpType::Copy | OpType::Link | OpType::Extract) {
+            fingerprint.hash = dedup::hash_file(path).ok();
+        }
+        Some(fingerprint)
+    }
+}
+
+/// A session as read back from the store.
+pub struct SessionLog {
+    pub info: SessionInfo,
+    pub operations: Vec<Operation>,
+    /// The fingerprint of each operation's result, where one was taken
+    pub fingerprints: Vec<Option<Fingerprint>>,
+    /// Operations that were started but never committed or aborted, e.g. because of a crash
+    pub pending: Vec<(u64, Operation)>,
+    /// What each pending operation had journaled as created, oldest first
+    pub created: HashMap<u64, Vec<PathBuf>>,
+    /// Positions in `operations` that were undone by a partial undo
+    pub reverted: BTreeSet<usize>,
+    /// When the whole session was undone
+    pub undone: Option<DateTime<Local>>,
+}
+
+impl SessionLog {
+    /// Number of operations that are still in effect.
+    pub fn remaining(&self) -> usize {
+        match self.undone {
+            Some(_) => 0,
+            None => self.operations.len() - self.reverted.len(),
+        }
+    }
+}
+
+/// Directory of session logs: one JSON Lines file per `run`, `watch` or
+/// `dupes` session, so any past session can be listed and undone.
+pub struct JournalStore {
+    dir: PathBuf,
+}
+
+impl JournalStore {
+    /// `$XDG_DATA_HOME/rarch/sessions` (usually `~/.local/share/rarch/sessions`).
+    pub fn default_dir() -> Option<PathBuf> {
+        let data_home = match std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+        };
+        Some(data_home.join("rarch").join("sessions"))
+    }
+
+    pub fn open(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The store in the default location.
+    pub fn open_default() -> anyhow::Result<Self> {
+        Ok(Self::open(Self::default_dir().context("Cannot locate the journal: $HOME is not set")?))
+    }
+
+    /// Starts a session. Its log is only created once something is recorded.
+    pub fn begin(&self, command: &str, path: &Path) -> Session {
+        self.start(SessionInfo {
+            id: String::new(),
+            started: Local::now(),
+            command: command.to_string(),
+            path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+            undoes: None,
+        })
+    }
+
+    /// Starts the session that journals an undo of `original`, so it can be redone.
+    pub fn begin_undo(&self, original: &SessionInfo) -> Session {
+        self.start(SessionInfo {
+            id: String::new(),
+            started: Local::now(),
+            command: "undo".to_string(),
+            path: original.path.clone(),
+            undoes: Some(original.id.clone()),
+        })
+    }
+
+    fn start(&self, mut info: SessionInfo) -> Session {
+        // The process ID keeps sessions of concurrent processes apart, the
+        // sequence number those of one process; the log is created with
+        // `create_new`, so a clash fails instead of sharing a file
+        static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        info.id = format!("{}-{}-{}", info.started.format("%Y%m%d-%H%M%S-%3f"), std::process::id(), sequence);
+        Session {
+            path: self.dir.join(format!("{}.jsonl", info.id)),
+            info,
+            next_id: AtomicU64::new(0),
+            header: Mutex::new(false),
+        }
+    }
+
+    /// Every recorded session, oldest first.
+    pub fn sessions(&self) -> anyhow::Result<Vec<SessionLog>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read the journal {:?}", self.dir)),
+        };
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "jsonl") {
+                if let Some(log) = read_log(&path)? {
+                    sessions.push(log);
+                }
+            }
+        }
+        sessions.sort_by(|a, b| a.info.started.cmp(&b.info.started).then_with(|| a.info.id.cmp(&b.info.id)));
+        Ok(sessions)
+    }
+
+    /// Loads the session whose ID is `id`, or the only one starting with it.
+    pub fn load(&self, id: &str) -> anyhow::Result<SessionLog> {
+        let mut matches: Vec<SessionLog> = self
+            .sessions()?
+            .into_iter()
+            .filter(|log| log.info.id.starts_with(id))
+            .collect();
+        if let Some(exact) = matches.iter().position(|log| log.info.id == id) {
+            return Ok(matches.swap_remove(exact));
+        }
+        match matches.len() {
+            0 => anyhow::bail!("No session {:?} (see 'rarch history')", id),
+            1 => Ok(matches.remove(0)),
+            n => anyhow::bail!("{:?} matches {} sessions, give more of the ID", id, n),
+        }
+    }
+
+    pub fn mark_undone(&self, id: &str) -> anyhow::Result<()> {
+        append_record(&self.log_path(id), &Record::Undone(Local::now()))
+    }
+
+    /// Marks the operation at position `index` of session `id` as undone.
+    pub fn mark_reverted(&self, id: &str, index: usize) -> anyhow::Result<()> {
+        append_record(&self.log_path(id), &Record::Reverted(index))
+    }
+
+    /// Settles an interrupted operation of session `id` as done, as `ops`.
+    pub fn commit(&self, id: &str, op_id: u64, ops: &[Operation]) -> anyhow::Result<()> {
+        append_record(&self.log_path(id), &Record::commit(op_id, ops.to_vec(), true))
+    }
+
+    /// Settles an interrupted operation of session `id` as not done.
+    pub fn abort(&self, id: &str, op_id: u64) -> anyhow::Result<()> {
+        append_record(&self.log_path(id), &Record::Abort(op_id))
+    }
+
+    fn log_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", id))
+    }
+}
+
+/// An open session that operations are journaled to as they are performed.
+///
+/// Each operation is written twice: an intent before any file is touched and
+/// a commit (or abort) once it is done, so `rarch recover` can settle
+/// whatever a crash interrupted in between.
+pub struct Session {
+    pub info: SessionInfo,
+    path: PathBuf,
+    next_id: AtomicU64,
+    /// Whether the log and its header line were written
+    header: Mutex<bool>,
+}
+
+impl Session {
+    /// Records that `op` is about to be performed. Returns the ID to settle it with.
+    pub fn intent(&self, op: &Operation) -> anyhow::Result<u64> {
+        self.ensure_header()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        append_record(&self.path, &Record::Intent { id, op: op.absolute() })?;
+        Ok(id)
+    }
+
+    /// Records that the pending operation `id` is about to create `path`.
+    pub fn created(&self, id: u64, path: &Path) -> anyhow::Result<()> {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        append_record(&self.path, &Record::Created { id, path })
+    }
+
+    /// Records that the operation `id` is done; `ops` are journaled for undo.
+    pub fn commit(&self, id: u64, ops: &[Operation]) -> anyhow::Result<()> {
+        append_record(&self.path, &Record::commit(id, ops.iter().map(Operation::absolute).collect(), true))
+    }
+
+    /// Records operations that were performed without an intent, such as the
+    /// reverts of an undo, which are journaled once they are done.
+    pub fn record_done(&self, ops: &[Operation]) -> anyhow::Result<()> {
+        self.ensure_header()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        append_record(&self.path, &Record::commit(id, ops.iter().map(Operation::absolute).collect(), false))
+    }
+
+    /// Records that the operation `id` was not performed.
+    pub fn abort(&self, id: u64) -> anyhow::Result<()> {
+        append_record(&self.path, &Record::Abort(id))
+    }
+
+    /// Creates the log with its header line on first use.
+    fn ensure_header(&self) -> anyhow::Result<()> {
+        let mut header = self.header.lock().unwrap();
+        if *header {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to create the journal {:?}", self.path))?;
+        writeln!(file, "{}", serde_json::to_string(&Record::Session(self.info.clone()))?)?;
+        file.sync_data().with_context(|| format!("Failed to sync the journal {:?}", self.path))?;
+        sync_parent(&self.path);
+        *header = true;
+        Ok(())
+    }
+}
+
+/// Appends one record to an existing log and syncs it to disk before returning.
+fn append_record(path: &Path, record: &Record) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open the journal {:?}", path))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    file.sync_data().with_context(|| format!("Failed to sync the journal {:?}", path))?;
+    Ok(())
+}
+
+/// Syncs the directory holding `path`, so a file created, renamed or removed
+/// there survives a crash. Best effort: not every file system supports it.
+pub fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if let Ok(dir) = std::fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Reads a session log, skipping lines that cannot be parsed (e.g. cut off by a crash).
+fn read_log(path: &Path) -> anyhow::Result<Option<SessionLog>> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let mut records = content.lines().filter_map(|line| serde_json::from_str::<Record>(line).ok());
+    let Some(Record::Session(info)) = records.next() else {
+        return Ok(None);
+    };
+    let mut log = SessionLog {
+        info,
+        operations: Vec::new(),
+        fingerprints: Vec::new(),
+        pending: Vec::new(),
+        created: HashMap::new(),
+        reverted: BTreeSet::new(),
+        undone: None,
+    };
+    let mut pending = BTreeMap::new();
+    for record in records {
+        match record {
+            Record::Session(_) => {}
+            Record::Intent { id, op } => {
+                pending.insert(id, op);
+            }
+            Record::Created { id, path } => log.created.entry(id).or_default().push(path),
+            Record::Commit { id, ops, mut fingerprints } => {
+                pending.remove(&id);
+                log.created.remove(&id);
+                fingerprints.resize(ops.len(), None);
+                log.operations.extend(ops);
+                log.fingerprints.extend(fingerprints);
+            }
+            Record::Abort(id) => {
+                pending.remove(&id);
+                log.created.remove(&id);
+            }
+            Record::Reverted(index) => {
+                log.reverted.insert(index);
+            }
+            Record::Undone(at) => log.undone = Some(at),
+        }
+    }
+    log.pending = pending.into_iter().collect();
+    Ok(Some(log))
+}
@@ -1,20 +1,40 @@
 mod ai;
+mod archive;
+mod cache;
+mod check;
 mod config;
+mod dedup;
+mod dupes;
 mod engine;
+mod index;
 mod journal;
+mod phash;
+mod recover;
+mod scanner;
+mod trash;
 mod ui;
+mod undo;
 
+#[cfg(test)]
+mod check_tests;
 #[cfg(test)]
 mod engine_tests;
+#[cfg(test)]
+mod phash_tests;
+#[cfg(test)]
+mod trash_tests;
+#[cfg(test)]
+mod undo_tests;
 
+use anyhow::Context;
 use clap::{Parser, Subcommand};
 use comfy_table::Table;
-use config::Config;
+use cache::HashCache;
+use config::{Config, DedupMode};
 use engine::Engine;
-use fs_extra::file::move_file;
-use fs_extra::file::CopyOptions;
 use indicatif::{ProgressBar, ProgressStyle};
-use journal::{JournalEntry, OpType};
+use index::TargetIndex;
+use journal::{Fingerprint, JournalEntry, JournalStore, OpType, Operation};
 use notify::{Config as NotifyConfig, RecursiveMode, Watcher};
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
@@ -46,12 +66,77 @@ enum Commands {
         /// Automatically proceed with changes without confirmation
         #[arg(short, long)]
         yes: bool,
+
+        /// Directory levels to scan (1 = top level only, 0 = unlimited); overrides `max_depth`
+        #[arg(long)]
+        depth: Option<usize>,
     },
-    /// Undo the last organization operation
+    /// Undo the last organization session, or the ones selected
     Undo {
-        /// Path to the journal file
-        #[arg(default_value = "rarch_journal.json")]
-        journal: PathBuf,
+        /// Journal file written by older versions (`rarch_journal.json`)
+        journal: Option<PathBuf>,
+
+        /// Session to undo (see 'rarch history'); a unique prefix of the ID is enough
+        #[arg(long, conflicts_with_all = ["journal", "last"])]
+        session: Option<String>,
+
+        /// Undo the N most recent sessions that were not undone yet, newest first
+        #[arg(long, conflicts_with = "journal")]
+        last: Option<usize>,
+
+        /// Only undo what this rule did (repeatable)
+        #[arg(long = "rule", value_name = "NAME")]
+        rules: Vec<String>,
+
+        /// Only undo files that were placed below this directory
+        #[arg(long, value_name = "DIR")]
+        target: Option<PathBuf>,
+
+        /// Only undo files whose original name matches this glob, e.g. "*.jpg"
+        #[arg(long, value_name = "GLOB")]
+        name: Option<String>,
+
+        /// Only undo these operations of the session, e.g. "3,5-7" (see 'rarch history --session')
+        #[arg(long, value_name = "LIST", conflicts_with = "last")]
+        ops: Option<String>,
+
+        /// Show what would be restored, missing, modified or in the way, without changing anything
+        #[arg(short, long)]
+        dry_run: bool,
+
+        /// Overwrite files that have since appeared at the original locations
+        #[arg(long)]
+        force: bool,
+    },
+    /// Reapply what an undo reverted
+    Redo {
+        /// Undo session to redo (see 'rarch history')
+        #[arg(long, conflicts_with = "last")]
+        session: Option<String>,
+
+        /// Redo the N most recent undos that were not redone yet, newest first
+        #[arg(long)]
+        last: Option<usize>,
+
+        /// Configuration whose conflict strategies apply when a target is taken (default: rename)
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
+    /// Finish or roll back operations that a crash interrupted
+    Recover {
+        /// Drop the operations that need checking by hand from the journal, once checked
+        #[arg(long)]
+        dismiss: bool,
+    },
+    /// List the journaled sessions that can be undone
+    History {
+        /// Show at most this many sessions, newest first
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// List the operations of this session instead
+        #[arg(long)]
+        session: Option<String>,
     },
     /// Launch the interactive TUI
     Ui {
@@ -68,6 +153,80 @@ enum Commands {
         /// Directory to watch
         #[arg(short, long, default_value = ".")]
         path: PathBuf,
+
+        /// Directory levels to watch (1 = top level only, 0 = unlimited); overrides `max_depth`
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+    /// Validate a configuration file without touching any files
+    Check {
+        /// Path to the configuration file
+        #[arg(short, long, default_value = "rarch.toml")]
+        config: PathBuf,
+    },
+    /// Show which rule a file matches and what would happen to it
+    Explain {
+        /// File to explain
+        file: PathBuf,
+
+        /// Path to the configuration file
+        #[arg(short, long, default_value = "rarch.toml")]
+        config: PathBuf,
+
+        /// Directory that would be organized
+        #[arg(short, long, default_value = ".")]
+        path: PathBuf,
+    },
+    /// Find duplicate files in a directory tree, without applying any rules
+    Dupes {
+        /// Directory to search
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Directory levels to search (0 = unlimited)
+        #[arg(long, default_value_t = 0)]
+        depth: usize,
+
+        /// Also look at hidden files and directories
+        #[arg(long)]
+        hidden: bool,
+
+        /// Which copy of each group to keep
+        #[arg(long, value_enum, default_value = "oldest")]
+        keep: dupes::Keep,
+
+        /// Prefer keeping copies inside this directory
+        #[arg(long)]
+        prefer: Option<PathBuf>,
+
+        /// Print the groups as JSON
+        #[arg(long, conflicts_with_all = ["interactive", "apply"])]
+        json: bool,
+
+        /// List images that look alike (same shot at another size or quality) instead
+        #[arg(long, conflicts_with_all = ["interactive", "apply"])]
+        similar: bool,
+
+        /// Largest perceptual hash distance (0-64) for `--similar`
+        #[arg(long, default_value_t = phash::DEFAULT_THRESHOLD, requires = "similar")]
+        threshold: u32,
+
+        /// Choose the copy to keep for every group
+        #[arg(short, long, requires = "apply")]
+        interactive: bool,
+
+        /// Replace the other copies with hard links, or trash them (journaled for 'rarch undo')
+        #[arg(long, value_enum)]
+        apply: Option<dupes::DupeAction>,
+
+        /// Apply without confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Manage the hash cache and target index in `$XDG_CACHE_HOME/rarch`
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
     },
     /// Generate shell completions
     Completions {
@@ -76,6 +235,95 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Drop stale entries to keep the cache small
+    Prune {
+        /// Remove hashes not used for this long ("30d", "6m")
+        #[arg(long, default_value = "90d")]
+        max_age: String,
+
+        /// Keep at most this many hashes, dropping the least recently used
+        #[arg(long)]
+        max_entries: Option<usize>,
+
+        /// Prune the cache directory and target index this configuration uses
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+    },
+}
+
+/// Lists what undoing the given operations would find, one row each.
+fn print_undo_preview<'a>(operations: impl Iterator<Item = (usize, &'a Operation, Option<Fingerprint>)>, force: bool) {
+    let mut table = Table::new();
+    table.set_header(vec!["#", "Action", "File", "Status", "Detail"]);
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for (index, op, fingerprint) in operations {
+        let (status, detail) = match undo::inspect(op, fingerprint.as_ref(), force) {
+            Ok(check) => (check.label(), check.detail().to_string()),
+            Err(e) => ("error", format!("{:#}", e)),
+        };
+        match counts.iter_mut().find(|(label, _)| *label == status) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((status, 1)),
+        }
+        table.add_row(vec![
+            (index + 1).to_string(),
+            op.op_type.label().to_string(),
+            op.from.display().to_string(),
+            status.to_string(),
+            detail,
+        ]);
+    }
+    if counts.is_empty() {
+        println!("No matching operations left to undo.");
+        return;
+    }
+    println!("{table}");
+    let counts: Vec<String> = counts.iter().map(|(label, count)| format!("{} {}", count, label)).collect();
+    println!("{}\n", counts.join(", "));
+}
+
+/// Prints one row per undone session and the operations that failed.
+fn print_undo_summary(summaries: &[(String, undo::UndoSummary)]) -> anyhow::Result<()> {
+    if summaries.iter().all(|(_, summary)| summary.selected == 0) {
+        println!("No matching operations left to undo.");
+        return Ok(());
+    }
+    let mut table = Table::new();
+    table.set_header(vec!["Session", "Selected", "Reverted", "Nothing to do", "Failed"]);
+    for (id, summary) in summaries {
+        table.add_row(vec![
+            id.clone(),
+            summary.selected.to_string(),
+            summary.reverted.to_string(),
+            summary.unchanged.to_string(),
+            summary.failed.len().to_string(),
+        ]);
+    }
+    println!("{table}");
+
+    let failed: Vec<_> = summaries.iter().flat_map(|(_, summary)| &summary.failed).collect();
+    for (path, reason) in &failed {
+        println!("Failed: {:?}: {}", path, reason);
+    }
+    if !failed.is_empty() {
+        anyhow::bail!("{} operations could not be undone; they stay in the journal, so run the undo again once fixed", failed.len());
+    }
+    Ok(())
+}
+
+/// Points out operations a crash left half-done before anything new is changed.
+fn warn_interrupted(store: &JournalStore) {
+    let pending: usize = store.sessions().map(|logs| logs.iter().map(|log| log.pending.len()).sum()).unwrap_or(0);
+    if pending > 0 {
+        println!(
+            "Warning: {} operations were interrupted and may be half-done. Run 'rarch recover' to settle them.",
+            pending
+        );
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
@@ -92,11 +340,20 @@ fn main() -> anyhow::Result<()> {
             path,
             dry_run,
             yes,
+            depth,
         } => {
-            let config = Config::from_file(config)?;
-            let engine = Engine::new(config, path);
+            let mut config = Config::from_file(config)?;
+            if depth.is_some() {
+                config.max_depth = depth;
+            }
+            let report_duplicates = config.dedup == DedupMode::Report;
+            let store = JournalStore::open_default()?;
+            warn_interrupted(&store);
+            let session = store.begin("run", &path);
+            let engine = Engine::new(config, path)?;
 
-            if dry_run {
+            // Report mode only plans, like a dry run
+            if dry_run || report_duplicates {
                 println!("--- Dry Run (No changes will be made) ---");
                 let pb = ProgressBar::new_spinner();
                 pb.set_style(ProgressStyle::with_template("{spinner:.green} {msg}").unwrap());
@@ -116,15 +373,12 @@ fn main() -> anyhow::Result<()> {
                     let mut saved_space = 0u64;
                     for op in &ops {
                         let rule_name = engine.match_rule::<fn(&str)>(&op.from, None).map(|r| r.name.as_str()).unwrap_or("Unknown");
-                        let action = match &op.op_type {
-                            OpType::Move => "Move",
-                            OpType::HardLink(_) => {
-                                if let Ok(meta) = std::fs::metadata(&op.from) {
-                                    saved_space += meta.len();
-                                }
-                                "Deduplicate"
-                            },
-                        };
+                        if op.op_type.duplicate_of().is_some() {
+                            if let Ok(meta) = std::fs::metadata(&op.from) {
+                                saved_space += meta.len();
+                            }
+                        }
+                        let action = op.op_type.label();
                         
                         table.add_row(vec![
                             op.from.file_name().unwrap().to_string_lossy().to_string(),
@@ -140,6 +394,9 @@ fn main() -> anyhow::Result<()> {
                         println!("  - Potential space saved: {:.2} MB", saved_space as f64 / 1024.0 / 1024.0);
                     }
                 }
+                if report_duplicates {
+                    print_duplicate_groups(&engine.duplicate_groups(&ops));
+                }
             } else {
                 let pb_dry = ProgressBar::new_spinner();
                 pb_dry.set_style(ProgressStyle::with_template("{spinner:.green} Analyzing files... {msg}").unwrap());
@@ -156,7 +413,7 @@ fn main() -> anyhow::Result<()> {
                 }
 
                 if !yes {
-                    println!("About to move {} files.", ops.len());
+                    println!("About to process {} files.", ops.len());
                     print!("Do you want to proceed? [y/N] ");
                     use std::io::Write;
                     std::io::stdout().flush()?;
@@ -177,8 +434,7 @@ fn main() -> anyhow::Result<()> {
                     .progress_chars("##-"),
                 );
 
-                let journal_path = PathBuf::from("rarch_journal.json");
-                let journal = engine.execute(Some(journal_path.clone()), |pos, _total, msg| {
+                let journal = engine.execute(Some(&session), |pos, _total, msg| {
                     pb.set_position(pos as u64);
                     pb.set_message(msg);
                 })?;
@@ -189,46 +445,395 @@ fn main() -> anyhow::Result<()> {
                     println!("No actions were performed.");
                 } else {
                     println!("\nSuccessfully organized {} files.", journal.operations.len());
-                    // Journal is already saved incrementally by engine.execute, 
-                    // but we can still save the full journal for historical backup if we like.
-                    journal.save(journal_path.clone())?;
-                    println!(
-                        "Journal saved to rarch_journal.json. You can undo this with 'rarch undo'."
+                    println!("Journaled as session {}. You can undo this with 'rarch undo'.", session.info.id);
+                }
+            }
+        }
+        Commands::Undo { journal, session, last, rules, target, name, ops, dry_run, force } => {
+            let filter = undo::UndoFilter::new(rules, target, name.as_deref(), ops.as_deref())?;
+            // Installations upgraded from older versions may only have their journal
+            let journal = match session.is_none() && last.is_none() {
+                true => journal.or_else(legacy_journal),
+                false => journal,
+            };
+            if let Some(journal) = journal {
+                println!("Undoing the legacy journal {:?}", journal);
+                let journal = JournalEntry::load(journal)?;
+                if dry_run {
+                    let selected = filter.select(&journal.operations)?.into_iter().rev();
+                    print_undo_preview(selected.map(|index| (index, &journal.operations[index], None)), force);
+                    return Ok(());
+                }
+                let summary = undo::revert_all(&journal.operations, &filter, force)?;
+                return print_undo_summary(&[("legacy journal".to_string(), summary)]);
+            }
+
+            let store = JournalStore::open_default()?;
+            warn_interrupted(&store);
+            let targets = match session {
+                Some(id) => {
+                    let log = store.load(&id)?;
+                    if log.info.undoes.is_some() {
+                        anyhow::bail!("Session {} is an undo; use 'rarch redo' to reapply it", log.info.id);
+                    }
+                    if log.remaining() == 0 {
+                        anyhow::bail!("Session {} was already undone", log.info.id);
+                    }
+                    vec![log]
+                }
+                // Most recent first, so later sessions are rolled back before the ones they built on
+                None => {
+                    let mut pending: Vec<_> = store
+                        .sessions()?
+                        .into_iter()
+                        .filter(|log| log.info.undoes.is_none() && log.remaining() > 0)
+                        .collect();
+                    pending.reverse();
+                    pending.truncate(last.unwrap_or(1));
+                    pending
+                }
+            };
+            if targets.is_empty() {
+                println!("Nothing to undo.");
+                return Ok(());
+            }
+
+            if dry_run {
+                for log in &targets {
+                    println!("Session {} ({} {:?}):", log.info.id, log.info.command, log.info.path);
+                    let selected = filter.select(&log.operations)?.into_iter().rev().filter(|index| !log.reverted.contains(index));
+                    print_undo_preview(
+                        selected.map(|index| (index, &log.operations[index], log.fingerprints.get(index).cloned().flatten())),
+                        force,
                     );
                 }
+                return Ok(());
+            }
+
+            let mut summaries = Vec::new();
+            for log in targets {
+                // Only journal failures end the undo early; failed operations are collected
+                let summary = undo::undo_session(&store, &log, &filter, force)
+                    .with_context(|| format!("Failed to undo session {}", log.info.id))?;
+                summaries.push((log.info.id, summary));
+            }
+            print_undo_summary(&summaries)?;
+        }
+        Commands::Redo { session, last, config } => {
+            let store = JournalStore::open_default()?;
+            warn_interrupted(&store);
+            let targets = match session {
+                Some(id) => {
+                    let log = store.load(&id)?;
+                    if log.info.undoes.is_none() {
+                        anyhow::bail!("Session {} is not an undo (see 'rarch history')", log.info.id);
+                    }
+                    if log.remaining() == 0 {
+                        anyhow::bail!("Session {} was already redone", log.info.id);
+                    }
+                    vec![log]
+                }
+                // The most recent undo first, as it rolled back the oldest of the sessions undone together
+                None => {
+                    let mut pending: Vec<_> = store
+                        .sessions()?
+                        .into_iter()
+                        .filter(|log| log.info.undoes.is_some() && log.remaining() > 0)
+                        .collect();
+                    pending.reverse();
+                    pending.truncate(last.unwrap_or(1));
+                    pending
+                }
+            };
+            if targets.is_empty() {
+                println!("Nothing to redo.");
+                return Ok(());
+            }
+            for log in &targets {
+                undo::ensure_redoable(log)?;
+            }
+            for log in targets {
+                let config = match &config {
+                    Some(path) => Config::from_file(path.clone())?,
+                    None => Config::default(),
+                };
+                let engine = Engine::new(config, log.info.path.clone())?;
+                let session = store.begin("redo", &log.info.path);
+                let summary = undo::redo_session(&store, &engine, &log, &session)
+                    .with_context(|| format!("Failed to redo session {}", log.info.id))?;
+                for problem in &summary.problems {
+                    println!("Not redone: {}", problem);
+                }
+                println!(
+                    "Redid session {} ({:?}): {} operations reapplied as session {}.",
+                    log.info.undoes.as_deref().unwrap_or_default(),
+                    log.info.path,
+                    summary.redone,
+                    session.info.id
+                );
             }
         }
-        Commands::Undo { journal } => {
-            let journal = JournalEntry::load(journal)?;
-            let options = CopyOptions::new();
+        Commands::Recover { dismiss } => {
+            let store = JournalStore::open_default()?;
             let mut count = 0;
-
-            // Reverse order undo
-            for op in journal.operations.iter().rev() {
-                if op.to.exists() {
-                    match &op.op_type {
-                        OpType::Move | OpType::HardLink(_) => {
-                            move_file(&op.to, &op.from, &options)?;
-                            count += 1;
+            let mut manual = 0;
+            for log in store.sessions()? {
+                for (op_id, op) in &log.pending {
+                    count += 1;
+                    let created = log.created.get(op_id).map(Vec::as_slice).unwrap_or_default();
+                    let outcome = recover::settle(op, created, log.info.started)
+                        .with_context(|| format!("Failed to recover the operation on {:?}", op.from))?;
+                    match outcome {
+                        recover::Outcome::Completed(done) => {
+                            store.commit(&log.info.id, *op_id, &done)?;
+                            println!("Completed: {:?} -> {:?}", op.from, done[0].to);
+                        }
+                        recover::Outcome::RolledBack => {
+                            store.abort(&log.info.id, *op_id)?;
+                            println!("Rolled back: {:?}", op.from);
+                        }
+                        // Stays pending, so it is reported again until dismissed
+                        recover::Outcome::Manual(reason) if dismiss => {
+                            store.abort(&log.info.id, *op_id)?;
+                            println!("Dismissed: {}", reason);
+                        }
+                        recover::Outcome::Manual(reason) => {
+                            manual += 1;
+                            println!("Check manually: {}", reason);
                         }
                     }
                 }
             }
-            println!("Undo complete. {} files restored.", count);
+            match count {
+                0 => println!("No interrupted operations found."),
+                n => println!("\nRecovered {} interrupted operations.", n - manual),
+            }
+            if manual > 0 {
+                println!("{} operations need checking; run 'rarch recover --dismiss' once they are resolved.", manual);
+            }
+        }
+        Commands::History { session: Some(id), .. } => {
+            let log = JournalStore::open_default()?.load(&id)?;
+            println!("Session {} ({} {:?}), started {}", log.info.id, log.info.command, log.info.path, log.info.started.format("%Y-%m-%d %H:%M:%S"));
+            let mut table = Table::new();
+            table.set_header(vec!["#", "Action", "Rule", "From", "To", "Status"]);
+            for (index, op) in log.operations.iter().enumerate() {
+                let undone = log.undone.is_some() || log.reverted.contains(&index);
+                table.add_row(vec![
+                    (index + 1).to_string(),
+                    op.op_type.label().to_string(),
+                    op.rule_name.clone().unwrap_or_default(),
+                    op.from.display().to_string(),
+                    op.to.display().to_string(),
+                    if undone { "undone" } else { "applied" }.to_string(),
+                ]);
+            }
+            println!("{table}");
+        }
+        Commands::History { limit, session: None } => {
+            let sessions = JournalStore::open_default()?.sessions()?;
+            if sessions.is_empty() {
+                println!("No sessions recorded yet.");
+                return Ok(());
+            }
+
+            let mut table = Table::new();
+            table.set_header(vec!["ID", "Started", "Command", "Path", "Operations", "Rules", "Status"]);
+            let skip = sessions.len().saturating_sub(limit);
+            for log in sessions.iter().skip(skip).rev() {
+                let mut rules: Vec<&str> = log.operations.iter().filter_map(|op| op.rule_name.as_deref()).collect();
+                rules.sort_unstable();
+                rules.dedup();
+                // An undo session is "undone" by redoing it
+                let verb = if log.info.undoes.is_some() { "redone" } else { "undone" };
+                let status = match log.undone {
+                    Some(at) => format!("{} {}", verb, at.format("%Y-%m-%d %H:%M")),
+                    None if !log.reverted.is_empty() => format!("{} of {} {}", log.reverted.len(), log.operations.len(), verb),
+                    None => "applied".to_string(),
+                };
+                table.add_row(vec![
+                    log.info.id.clone(),
+                    log.info.started.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    log.info.command.clone(),
+                    log.info.path.display().to_string(),
+                    log.operations.len().to_string(),
+                    rules.join(", "),
+                    status,
+                ]);
+            }
+            println!("{table}");
+        }
+        Commands::Check { config } => {
+            let source = std::fs::read_to_string(&config)
+                .with_context(|| format!("Failed to read config {:?}", config))?;
+            let diagnostics = check::check_source(&source);
+            for diagnostic in &diagnostics {
+                println!("{}:{}", config.display(), diagnostic);
+            }
+
+            let errors = diagnostics.iter().filter(|d| d.severity == check::Severity::Error).count();
+            let warnings = diagnostics.len() - errors;
+            if diagnostics.is_empty() {
+                println!("{}: no problems found.", config.display());
+            } else {
+                println!("\n{} error(s), {} warning(s).", errors, warnings);
+            }
+            if errors > 0 {
+                anyhow::bail!("{:?} is not a valid configuration", config);
+            }
+        }
+        Commands::Explain { file, config, path } => {
+            let config = Config::from_file(config)?;
+            let engine = Engine::new(config, path)?;
+            let explanation = engine.explain(&file)?;
+
+            println!("File: {}", file.display());
+            println!("  Size:       {} bytes", explanation.size);
+            match (&explanation.detected_mime, &explanation.detected_ext) {
+                (Some(mime), Some(ext)) => println!("  Detected:   {} (.{})", mime, ext),
+                _ => println!("  Detected:   unknown (no magic number recognized)"),
+            }
+            println!("  Extension:  {}", explanation.file_ext.as_deref().unwrap_or("(none)"));
+            if !explanation.in_scan {
+                println!("  Note:       not picked up by a scan (excluded, ignored, hidden or beyond max_depth)");
+            }
+
+            let mut table = Table::new();
+            table.set_header(vec!["#", "Rule", "Check", "Result"]);
+            for (index, rule) in explanation.rules.iter().enumerate() {
+                let verdict = if explanation.matched_rule.as_deref() == Some(rule.name.as_str()) && rule.matched {
+                    "MATCH"
+                } else if rule.matched {
+                    "match (shadowed)"
+                } else {
+                    "no match"
+                };
+                table.add_row(vec![(index + 1).to_string(), rule.name.clone(), String::new(), verdict.to_string()]);
+                for step in &rule.steps {
+                    let result = match step.result {
+                        Some(true) => "yes",
+                        Some(false) => "no",
+                        None => "not evaluated",
+                    };
+                    table.add_row(vec![String::new(), String::new(), step.label.clone(), result.to_string()]);
+                }
+            }
+            println!("{table}");
+
+            let Some(rule_name) = &explanation.matched_rule else {
+                println!("\nResult: no rule matches, the file stays where it is.");
+                return Ok(());
+            };
+            println!("\nResult: rule '{}'", rule_name);
+            if let Some(target) = &explanation.target {
+                println!("  Target:     {}", target.display());
+            }
+            match &explanation.final_target {
+                Some(Ok(Some(final_to))) if Some(final_to) == explanation.target.as_ref() => {
+                    if final_to.exists() {
+                        println!("  Conflict:   target exists and would be overwritten");
+                    } else {
+                        println!("  Conflict:   none");
+                    }
+                }
+                Some(Ok(Some(final_to))) => println!("  Conflict:   target exists, would be renamed to {}", final_to.display()),
+                Some(Ok(None)) => println!("  Conflict:   target exists, file would be skipped"),
+                Some(Err(e)) => println!("  Conflict:   {}", e),
+                None => {}
+            }
+            match &explanation.duplicate_of {
+                Some((original, OpType::AlreadyPresent)) => {
+                    println!("  Dedup:      already present as {}, would be skipped", original.display())
+                }
+                Some((original, op_type)) => {
+                    let how = match op_type {
+                        OpType::Reflink(_) => "reflinked",
+                        OpType::SoftLink(_) => "replaced by a symlink",
+                        OpType::Deleted(_) => "deleted",
+                        _ => "hard-linked",
+                    };
+                    println!("  Dedup:      identical to {}, would be {}", original.display(), how)
+                }
+                None => println!("  Dedup:      no duplicate in this run"),
+            }
+        }
+        Commands::Dupes { path, depth, hidden, keep, prefer, json, similar, threshold, interactive, apply, yes } => {
+            let prefer = match prefer {
+                Some(dir) => Some(dir.canonicalize().with_context(|| format!("Cannot find directory {:?}", dir))?),
+                None => None,
+            };
+            let config = Config {
+                max_depth: Some(depth),
+                include_hidden: hidden,
+                hash_cache: true,
+                ..Default::default()
+            };
+            let store = JournalStore::open_default()?;
+            warn_interrupted(&store);
+            let session = store.begin("dupes", &path);
+            let engine = Engine::new(config, path)?;
+            if similar {
+                if !cfg!(feature = "phash") {
+                    anyhow::bail!("Perceptual hashing is disabled (rebuild with --features phash)");
+                }
+                return dupes::run_similar(&engine, threshold, json);
+            }
+            let options = dupes::DupesOptions {
+                keep,
+                prefer,
+                json,
+                interactive,
+                action: apply,
+                yes,
+                session,
+            };
+            dupes::run_dupes(&engine, &options)?;
+            engine.save_hash_cache()?;
+        }
+        Commands::Cache { action: CacheAction::Prune { max_age, max_entries, config } } => {
+            let max_age = Engine::parse_age(&max_age)
+                .with_context(|| format!("Invalid age {:?} (expected e.g. \"30d\")", max_age))?;
+            let config = config.map(Config::from_file).transpose()?.unwrap_or_default();
+            let cache_dir = config.cache_dir.or_else(cache::cache_dir).context("Cannot locate the cache: $HOME is not set")?;
+            let hashes = HashCache::load(HashCache::path_in(&cache_dir));
+            let removed = hashes.prune(max_age, max_entries)?;
+            hashes.save()?;
+            println!("Hash cache: removed {} entries, {} remaining.", removed, hashes.len());
+
+            let index_path = config.dedup_index.unwrap_or_else(|| TargetIndex::path_in(&cache_dir));
+            if index_path.exists() {
+                let mut target_index = TargetIndex::load(&index_path);
+                let removed = target_index.prune();
+                target_index.save(&index_path)?;
+                println!("Target index: removed {} missing files, {} remaining.", removed, target_index.len());
+            }
         }
         Commands::Ui { path } => {
             ui::run_ui(path)?;
         }
-        Commands::Watch { config, path } => {
-            let config_data = Config::from_file(config)?;
-            let engine = Engine::new(config_data, path.clone());
+        Commands::Watch { config, path, depth } => {
+            let mut config_data = Config::from_file(config)?;
+            if depth.is_some() {
+                config_data.max_depth = depth;
+            }
+            // Nested levels need a recursive watch; the scanner filters events by depth
+            let recursive_mode = if config_data.max_depth.unwrap_or(1) == 1 {
+                RecursiveMode::NonRecursive
+            } else {
+                RecursiveMode::Recursive
+            };
+            if config_data.dedup == DedupMode::Report {
+                anyhow::bail!("dedup = \"report\" only plans; use `rarch run` to list the duplicates");
+            }
+            let engine = Engine::new(config_data, path.clone())?;
             let (tx, rx) = channel();
 
             let mut watcher = notify::RecommendedWatcher::new(tx, NotifyConfig::default())?;
-            watcher.watch(&path, RecursiveMode::NonRecursive)?;
+            watcher.watch(&path, recursive_mode)?;
 
-            let mut journal = JournalEntry::new();
-            let journal_path = PathBuf::from("rarch_journal.json");
+            let store = JournalStore::open_default()?;
+            warn_interrupted(&store);
+            let session = store.begin("watch", &path);
 
             println!(
                 "Watching {:?} for new files... (Press Ctrl+C to stop)",
@@ -238,11 +843,15 @@ fn main() -> anyhow::Result<()> {
             for res in rx {
                 match res {
                     Ok(event) => {
+                        for changed in &event.paths {
+                            if engine.reload_ignore_file(changed) {
+                                println!("Reloaded {:?}", changed);
+                            }
+                        }
                         if event.kind.is_create() || event.kind.is_modify() {
                             for file_path in event.paths {
-                                if let Ok(Some(mut op)) = engine.process_single_file::<fn(&str)>(file_path.clone(), None)
+                                if let Ok(Some(op)) = engine.process_single_file::<fn(&str)>(file_path.clone(), None)
                                 {
-                                    let options = CopyOptions::new();
                                     let target_parent = op.to.parent().unwrap();
                                     if !target_parent.exists() {
                                         let _ = std::fs::create_dir_all(target_parent);
@@ -250,15 +859,18 @@ fn main() -> anyhow::Result<()> {
                                     
                                     // Handle conflicts in Watch mode too
                                     if let Ok(Some(final_to)) = engine.handle_conflict(&op) {
-                                        if move_file(&op.from, &final_to, &options).is_ok() {
-                                            op.to = final_to;
-                                            println!(
-                                                "Auto-organized: {:?} -> {:?}",
-                                                op.from.file_name().unwrap(),
-                                                op.to
-                                            );
-                                            journal.operations.push(op);
-                                            let _ = journal.save(journal_path.clone());
+                                        match engine.apply_journaled(&op, &final_to, Some(&session)) {
+                                            Ok(_) => {
+                                                println!(
+                                                    "Auto-organized: {:?} -> {:?}",
+                                                    op.from.file_name().unwrap(),
+                                                    final_to
+                                                );
+                                                if let Err(e) = engine.save_hash_cache() {
+                                                    println!("Warning: {:#}", e);
+                                                }
+                                            }
+                                            Err(e) => println!("Failed to organize {:?}: {:#}", op.from, e),
                                         }
                                     }
                                 }
@@ -273,3 +885,36 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// `./rarch_journal.json`, written by older versions, if the session store
+/// holds no sessions yet.
+fn legacy_journal() -> Option<PathBuf> {
+    let path = PathBuf::from("rarch_journal.json");
+    if !path.is_file() {
+        return None;
+    }
+    let no_sessions = match JournalStore::open_default() {
+        Ok(store) => store.sessions().is_ok_and(|sessions| sessions.is_empty()),
+        Err(_) => true,
+    };
+    no_sessions.then_some(path)
+}
+
+/// Lists the duplicate sets found in report mode, original first.
+fn print_duplicate_groups(groups: &[Vec<PathBuf>]) {
+    if groups.is_empty() {
+        println!("No duplicates found.");
+        return;
+    }
+    let mut wasted = 0u64;
+    println!("\nDuplicates (report only, nothing was changed):");
+    for (index, group) in groups.iter().enumerate() {
+        let size = std::fs::metadata(&group[0]).map(|m| m.len()).unwrap_or(0);
+        wasted += size * (group.len() as u64 - 1);
+        println!("  [{}] {} files of {} bytes", index + 1, group.len(), size);
+        for path in group {
+            println!("      {}", path.display());
+        }
+    }
+    println!("  Space held by duplicates: {:.2} MB", wasted as f64 / 1024.0 / 1024.0);
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Largest Hamming distance (out of 64 bits) at which two images count as similar by default.
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// Number of bits in which two perceptual hashes differ.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// True if the file's content is detected as an image.
+pub fn is_image(path: &Path) -> bool {
+    infer::get_from_path(path)
+        .ok()
+        .flatten()
+        .is_some_and(|kind| kind.mime_type().starts_with("image/"))
+}
+
+/// Metric tree over hashes by Hamming distance. Children are keyed by their
+/// distance to the parent, so by the triangle inequality a lookup only needs
+/// to follow edges within `threshold` of the query's own distance.
+#[derive(Default)]
+pub struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    id: usize,
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    /// Adds `hash`, reported as `id` by lookups.
+    pub fn insert(&mut self, hash: u64, id: usize) {
+        let new = self.nodes.len();
+        let mut node = 0;
+        while node < new {
+            let edge = distance(self.nodes[node].hash, hash);
+            match self.nodes[node].children.iter().find(|(d, _)| *d == edge) {
+                Some(&(_, child)) => node = child,
+                None => {
+                    self.nodes[node].children.push((edge, new));
+                    break;
+                }
+            }
+        }
+        self.nodes.push(BkNode { hash, id, children: Vec::new() });
+    }
+
+    /// Ids of the hashes at most `threshold` bits away from `hash`.
+    pub fn within(&self, hash: u64, threshold: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending: Vec<usize> = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(index) = pending.pop() {
+            let node = &self.nodes[index];
+            let d = distance(node.hash, hash);
+            if d <= threshold {
+                found.push(node.id);
+            }
+            pending.extend(node.children.iter().filter(|(edge, _)| edge.abs_diff(d) <= threshold).map(|(_, child)| *child));
+        }
+        found
+    }
+}
+
+/// Groups images whose hashes lie within `threshold` of each other. Similarity
+/// is chained, so A and C share a group when both are close to B. Groups hold
+/// at least two paths and are sorted like those of `dedup::find_duplicates`.
+pub fn group_similar(hashes: &[(PathBuf, u64)], threshold: u32) -> Vec<Vec<PathBuf>> {
+    // Union-find over the close pairs, each image looked up among the ones before it
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut tree = BkTree::default();
+    for (i, (_, hash)) in hashes.iter().enumerate() {
+        for j in tree.within(*hash, threshold) {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a] = b;
+        }
+        tree.insert(*hash, i);
+    }
+
+    let mut groups: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for (i, (path, _)) in hashes.iter().enumerate() {
+        groups.entry(root(&mut parent, i)).or_default().push(path.clone());
+    }
+    let mut groups: Vec<Vec<PathBuf>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort();
+    groups
+}
+
+#[cfg(feature = "phash")]
+mod imp {
+    use anyhow::Context;
+    use std::path::Path;
+
+    /// 64-bit difference hash (dHash) of an image: the picture is shrunk to 9x8
+    /// grey pixels and each bit records whether a pixel is brighter than its
+    /// right neighbour. Resizing and recompression barely change it.
+    pub fn dhash(path: &Path) -> anyhow::Result<u64> {
+        let image = image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()
+            .with_context(|| format!("Cannot decode image {:?}", path))?;
+        let small = image.thumbnail_exact(9, 8).to_luma8();
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                hash <<= 1;
+                if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                    hash |= 1;
+                }
+            }
+        }
+        Ok(hash)
+    }
+}
+
+#[cfg(not(feature = "phash"))]
+mod imp {
+    use std::path::Path;
+
+    pub fn dhash(_path: &Path) -> anyhow::Result<u64> {
+        anyhow::bail!("Perceptual hashing is disabled (rebuild with --features phash)")
+    }
+}
+
+pub use imp::dhash;
@@ -0,0 +1,44 @@
+#[cfg(test)]
+mod tests {
+    use crate::phash::{distance, group_similar, BkTree};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_group_similar_chains_close_hashes() {
+        let hashes = vec![
+            (PathBuf::from("c.jpg"), 0b0000_0111),
+            (PathBuf::from("a.jpg"), 0b0000_0000),
+            (PathBuf::from("b.jpg"), 0b0000_0011),
+            (PathBuf::from("far.jpg"), u64::MAX),
+        ];
+        // a~b and b~c, so all three belong together even though a and c differ in 3 bits
+        let groups = group_similar(&hashes, 2);
+        assert_eq!(groups, vec![vec![PathBuf::from("a.jpg"), PathBuf::from("b.jpg"), PathBuf::from("c.jpg")]]);
+        assert!(group_similar(&hashes, 0).is_empty());
+    }
+
+    #[test]
+    fn test_bk_tree_finds_the_same_hashes_as_a_full_scan() {
+        // A fixed pseudo-random sequence with repeats and near neighbours
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut hashes = Vec::new();
+        for i in 0..500 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            hashes.push(if i > 0 && i % 5 == 0 { hashes[i / 2] ^ (state & 0x0101) } else { state });
+        }
+        let mut tree = BkTree::default();
+        for (id, hash) in hashes.iter().enumerate() {
+            tree.insert(*hash, id);
+        }
+        for threshold in [0, 3, 10] {
+            for query in hashes.iter().step_by(7) {
+                let mut found = tree.within(*query, threshold);
+                found.sort();
+                let expected: Vec<usize> = (0..hashes.len()).filter(|&id| distance(hashes[id], *query) <= threshold).collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
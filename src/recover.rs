@@ -0,0 +1,155 @@
+use crate::archive;
+use crate::dedup;
+use crate::engine::{link_temp, same_file, temp_sibling};
+use crate::journal::{OpType, Operation};
+use crate::trash::Trash;
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use std::path::PathBuf;
+
+/// How an operation interrupted by a crash was settled.
+pub enum Outcome {
+    /// It had taken effect (and was finished if needed); holds it as performed
+    Completed(Vec<Operation>),
+    /// It had not taken effect, or its partial result was removed
+    RolledBack,
+    /// The state is ambiguous and was left alone for the user to check
+    Manual(String),
+}
+
+/// Looks at the files an interrupted operation touched and either finishes
+/// or rolls it back. `op.to` is the final target chosen before it started,
+/// `created` what it journaled as created so far, and `started` is when its
+/// session began. Nothing that could be the only copy of a file is ever removed.
+pub fn settle(op: &Operation, created: &[PathBuf], started: DateTime<Local>) -> anyhow::Result<Outcome> {
+    let from_exists = op.from.symlink_metadata().is_ok();
+    let to_exists = op.to.symlink_metadata().is_ok();
+    match &op.op_type {
+        // The result is created at `to` first, then `from` is removed
+        OpType::Move | OpType::HardLink(_) | OpType::Reflink(_) | OpType::SoftLink(_) => match (from_exists, to_exists) {
+            (true, false) => Ok(Outcome::RolledBack),
+            (false, false) => Ok(Outcome::Manual(format!("neither {:?} nor {:?} exists", op.from, op.to))),
+            (false, true) => Ok(Outcome::Completed(vec![op.clone()])),
+            (true, true) => {
+                if dedup::same_content(&op.from, &op.to)? {
+                    std::fs::remove_file(&op.from)?;
+                    Ok(Outcome::Completed(vec![op.clone()]))
+                } else if is_partial_copy(op, started) {
+                    std::fs::remove_file(&op.to)?;
+                    Ok(Outcome::RolledBack)
+                } else {
+                    Ok(Outcome::Manual(format!("{:?} differs from {:?}; both were kept", op.to, op.from)))
+                }
+            }
+        },
+        OpType::Deleted(_) if from_exists => Ok(Outcome::RolledBack),
+        OpType::Deleted(_) => Ok(Outcome::Completed(vec![op.clone()])),
+        OpType::Copy if to_exists && dedup::same_content(&op.from, &op.to)? => Ok(Outcome::Completed(vec![op.clone()])),
+        OpType::Copy if to_exists && is_partial_copy(op, started) => {
+            std::fs::remove_file(&op.to)?;
+            Ok(Outcome::RolledBack)
+        }
+        OpType::Copy if to_exists => Ok(Outcome::Manual(format!("{:?} differs from {:?}; both were kept", op.to, op.from))),
+        OpType::Link if to_exists && same_file(&op.from, &op.to) => Ok(Outcome::Completed(vec![op.clone()])),
+        OpType::Symlink if op.to.is_symlink() => Ok(Outcome::Completed(vec![op.clone()])),
+        OpType::Copy | OpType::Link | OpType::Symlink => Ok(Outcome::RolledBack),
+        OpType::TwinLink => {
+            let tmp = link_temp(&op.from);
+            if tmp.symlink_metadata().is_ok() {
+                std::fs::remove_file(&tmp)?;
+            }
+            match same_file(&op.from, &op.to) {
+                true => Ok(Outcome::Completed(vec![op.clone()])),
+                false => Ok(Outcome::RolledBack),
+            }
+        }
+        OpType::Trash(_) if from_exists => Ok(Outcome::RolledBack),
+        OpType::Trash(_) => match Trash::home()?.find(&op.from, started.naive_local()) {
+            Some((trashed_path, info_path)) => Ok(Outcome::Completed(vec![Operation {
+                to: trashed_path,
+                op_type: OpType::Trash(info_path),
+                ..op.clone()
+            }])),
+            None => Ok(Outcome::Manual(format!("{:?} is gone but was not found in the trash", op.from))),
+        },
+        // Members are appended at the end, so an archived copy is the last one,
+        // under the file's name or a numbered variant of it
+        OpType::Archive { .. } => {
+            let file_name = op.from.file_name().unwrap_or_default().to_string_lossy();
+            let last = archive::members(&op.to)
+                .ok()
+                .and_then(|members| members.last().cloned())
+                .filter(|member| archive::named_after(member, &file_name));
+            let done = |member: String| {
+                Outcome::Completed(vec![Operation {
+                    op_type: OpType::Archive { archive: op.to.clone(), member },
+                    ..op.clone()
+                }])
+            };
+            match last {
+                Some(member) if !from_exists => Ok(done(member)),
+                None if !from_exists => Ok(Outcome::Manual(format!("{:?} is gone but was not found in {:?}", op.from, op.to))),
+                Some(member) if archived_copy_matches(op, &member)? => {
+                    std::fs::remove_file(&op.from)?;
+                    Ok(done(member))
+                }
+                _ => Ok(Outcome::RolledBack),
+            }
+        }
+        // The archive is only trashed once everything is unpacked, so while it
+        // is in place the extraction was cut short and is undone
+        OpType::Extract if from_exists => {
+            for path in created.iter().rev() {
+                match path.symlink_metadata() {
+                    // Kept if something else has been put in it since
+                    Ok(metadata) if metadata.is_dir() => {
+                        let _ = std::fs::remove_dir(path);
+                    }
+                    Ok(_) => std::fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?,
+                    Err(_) => {}
+                }
+            }
+            Ok(Outcome::RolledBack)
+        }
+        OpType::Extract => match Trash::home()?.find(&op.from, started.naive_local()) {
+            Some((trashed_path, info_path)) => {
+                // Created directories are journaled too, so undo removes them once emptied
+                let mut done: Vec<Operation> = created
+                    .iter()
+                    .filter(|path| path.is_file() || path.is_dir())
+                    .map(|path| Operation { to: path.clone(), ..op.clone() })
+                    .collect();
+                done.push(Operation { to: trashed_path, op_type: OpType::Trash(info_path), ..op.clone() });
+                Ok(Outcome::Completed(done))
+            }
+            None => Ok(Outcome::Manual(format!(
+                "{:?} is gone but was not found in the trash; the files unpacked into {:?} were kept",
+                op.from, op.to
+            ))),
+        },
+        OpType::AlreadyPresent => Ok(Outcome::RolledBack),
+    }
+}
+
+/// True if `to` looks like a copy of `from` cut short: a regular file that
+/// is smaller and was written during the session.
+fn is_partial_copy(op: &Operation, started: DateTime<Local>) -> bool {
+    let (Ok(from), Ok(to)) = (std::fs::metadata(&op.from), std::fs::symlink_metadata(&op.to)) else {
+        return false;
+    };
+    // File system timestamps are coarse (2 seconds on FAT), so allow some slack
+    let since = started - chrono::Duration::seconds(2);
+    to.is_file() && to.len() < from.len() && to.modified().is_ok_and(|modified| DateTime::<Local>::from(modified) >= since)
+}
+
+/// True if the archive member `member` holds the same bytes as `op.from`.
+fn archived_copy_matches(op: &Operation, member: &str) -> anyhow::Result<bool> {
+    let tmp = temp_sibling(&op.from, "rarch-recover");
+    if archive::extract_member(&op.to, member, &tmp).is_err() {
+        let _ = std::fs::remove_file(&tmp);
+        return Ok(false);
+    }
+    let same = dedup::same_content(&tmp, &op.from);
+    std::fs::remove_file(&tmp)?;
+    Ok(same?)
+}
@@ -0,0 +1,243 @@
+use crate::config::Config;
+use anyhow::Context;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use walkdir::{DirEntry, WalkDir};
+
+/// Name of the per-directory ignore file (gitignore syntax).
+pub const IGNORE_FILE_NAME: &str = ".rarchignore";
+
+/// Walks the base directory according to the scan options in `Config`.
+///
+/// The target directory of every rule is excluded from the walk so that files
+/// which have already been organized are never processed again. Paths matched
+/// by the global `exclude` list or by any `.rarchignore` file are skipped too.
+pub struct Scanner {
+    base_dir: PathBuf,
+    base_canonical: PathBuf,
+    max_depth: usize,
+    include_hidden: bool,
+    follow_symlinks: bool,
+    cross_mounts: bool,
+    excluded_dirs: Vec<PathBuf>,
+    exclude: Gitignore,
+    // Parsed `.rarchignore` files, keyed by directory relative to the base
+    ignore_files: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl Scanner {
+    pub fn new(config: &Config, base_dir: &Path) -> anyhow::Result<Self> {
+        let base_canonical = base_dir
+            .canonicalize()
+            .unwrap_or_else(|_| base_dir.to_path_buf());
+
+        let max_depth = match config.max_depth {
+            None => 1,
+            Some(0) => usize::MAX,
+            Some(depth) => depth,
+        };
+
+        let mut excluded_dirs = Vec::new();
+        for rule in &config.rules {
+            let Some(root) = target_root(&rule.target) else {
+                continue;
+            };
+            let relative = if root.is_absolute() {
+                let root = root.canonicalize().unwrap_or(root);
+                match root.strip_prefix(&base_canonical) {
+                    Ok(rel) => rel.to_path_buf(),
+                    Err(_) => continue, // Outside of the scanned tree
+                }
+            } else {
+                root
+            };
+            if relative.as_os_str().is_empty() || excluded_dirs.contains(&relative) {
+                continue;
+            }
+            excluded_dirs.push(relative);
+        }
+
+        let mut exclude = GitignoreBuilder::new(&base_canonical);
+        for pattern in &config.exclude {
+            exclude
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid exclude pattern {:?}", pattern))?;
+        }
+        let exclude = exclude.build()?;
+
+        Ok(Self {
+            base_dir: base_dir.to_path_buf(),
+            base_canonical,
+            max_depth,
+            include_hidden: config.include_hidden,
+            follow_symlinks: config.follow_symlinks,
+            cross_mounts: config.cross_mounts,
+            excluded_dirs,
+            exclude,
+            ignore_files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Collects every regular file below the base directory that passes the scan filters.
+    pub fn scan(&self) -> Vec<PathBuf> {
+        WalkDir::new(&self.base_dir)
+            .max_depth(self.max_depth)
+            .follow_links(self.follow_symlinks)
+            .same_file_system(!self.cross_mounts)
+            .into_iter()
+            .filter_entry(|e| self.keep_entry(e))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.path().to_path_buf())
+            .collect()
+    }
+
+    /// Checks a single path (e.g. from a watch event) against the same filters used by `scan`.
+    pub fn accepts(&self, path: &Path) -> bool {
+        let Some(relative) = self.relative(path) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        if depth == 0 || depth > self.max_depth {
+            return false;
+        }
+        if !self.include_hidden && relative.components().any(|c| is_hidden_name(c.as_os_str())) {
+            return false;
+        }
+        if !self.follow_symlinks {
+            // Reject paths that reach the file through a symlinked directory
+            let mut current = self.base_dir.clone();
+            for component in relative.parent().into_iter().flat_map(|p| p.components()) {
+                current.push(component);
+                if current.is_symlink() {
+                    return false;
+                }
+            }
+        }
+        !self.is_excluded(&relative) && !self.is_ignored(&relative, path.is_dir())
+    }
+
+    fn keep_entry(&self, entry: &DirEntry) -> bool {
+        if entry.depth() == 0 {
+            return true;
+        }
+        if !self.include_hidden && is_hidden_name(entry.file_name()) {
+            return false;
+        }
+        let Ok(relative) = entry.path().strip_prefix(&self.base_dir) else {
+            return true;
+        };
+        let is_dir = entry.file_type().is_dir();
+        if is_dir && self.is_excluded(relative) {
+            return false;
+        }
+        !self.is_ignored(relative, is_dir)
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        self.excluded_dirs.iter().any(|dir| relative.starts_with(dir))
+    }
+
+    /// Applies the global `exclude` patterns and every `.rarchignore` between
+    /// the base directory and the path. Deeper ignore files take precedence,
+    /// so a nested `!pattern` can re-include what a parent ignored.
+    fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        if relative.file_name().is_some_and(|name| name == IGNORE_FILE_NAME) {
+            return true;
+        }
+        let full_path = self.base_canonical.join(relative);
+        if self
+            .exclude
+            .matched_path_or_any_parents(&full_path, is_dir)
+            .is_ignore()
+        {
+            return true;
+        }
+
+        for dir in relative.ancestors().skip(1) {
+            let Some(ignore_file) = self.ignore_file(dir) else {
+                continue;
+            };
+            match ignore_file.matched_path_or_any_parents(&full_path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    fn ignore_file(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut cache = self.ignore_files.lock().unwrap();
+        cache
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let root = self.base_canonical.join(dir);
+                let file = root.join(IGNORE_FILE_NAME);
+                if !file.is_file() {
+                    return None;
+                }
+                let (gitignore, err) = Gitignore::new(&file);
+                if let Some(e) = err {
+                    eprintln!("Warning: problem reading {:?}: {}", file, e);
+                }
+                Some(Arc::new(gitignore))
+            })
+            .clone()
+    }
+
+    /// Forgets the cached rules of `path` if it is a `.rarchignore` file, so
+    /// the next lookup reads it again. Returns true if it was one.
+    pub fn reload_ignore_file(&self, path: &Path) -> bool {
+        if path.file_name().is_none_or(|name| name != IGNORE_FILE_NAME) {
+            return false;
+        }
+        if let Some(dir) = self.relative(path).as_deref().and_then(Path::parent) {
+            self.ignore_files.lock().unwrap().remove(dir);
+        }
+        true
+    }
+
+    /// Returns the path relative to the base directory, if it lives inside it.
+    pub fn relative(&self, path: &Path) -> Option<PathBuf> {
+        if let Ok(rel) = path.strip_prefix(&self.base_dir) {
+            return Some(rel.to_path_buf());
+        }
+        if let Ok(rel) = path.strip_prefix(&self.base_canonical) {
+            return Some(rel.to_path_buf());
+        }
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let parent = parent.canonicalize().ok()?;
+        let rel = parent.strip_prefix(&self.base_canonical).ok()?;
+        Some(rel.join(path.file_name()?))
+    }
+}
+
+/// Returns the static leading part of a target template, i.e. every path
+/// component before the first placeholder. `None` if the target starts with
+/// a placeholder or points at the base directory itself.
+pub fn target_root(target: &str) -> Option<PathBuf> {
+    let mut root = PathBuf::new();
+    for component in Path::new(target).components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) if part.to_string_lossy().contains("${") => break,
+            other => root.push(other),
+        }
+    }
+    if root.as_os_str().is_empty() || root.starts_with("..") {
+        None
+    } else {
+        Some(root)
+    }
+}
+
+fn is_hidden_name(name: &std::ffi::OsStr) -> bool {
+    name.to_str().is_some_and(|s| s.starts_with('.') && s != "." && s != "..")
+}
@@ -0,0 +1,163 @@
+use anyhow::Context;
+use chrono::{Local, NaiveDateTime};
+use fs_extra::file::{move_file, CopyOptions};
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A trash directory laid out according to the freedesktop.org Trash
+/// specification: trashed files live in `files/`, and for each of them a
+/// `.trashinfo` file in `info/` records the original path and deletion date.
+pub struct Trash {
+    root: PathBuf,
+}
+
+impl Trash {
+    /// The home trash, `$XDG_DATA_HOME/Trash` (usually `~/.local/share/Trash`).
+    pub fn home() -> anyhow::Result<Self> {
+        let data_home = match std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                let home = std::env::var_os("HOME").context("Cannot locate the trash: $HOME is not set")?;
+                PathBuf::from(home).join(".local/share")
+            }
+        };
+        Ok(Self::with_root(data_home.join("Trash")))
+    }
+
+    pub fn with_root(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn files_dir(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    pub fn info_dir(&self) -> PathBuf {
+        self.root.join("info")
+    }
+
+    /// Moves `path` into the trash. Returns the new location of the file and
+    /// the path of its `.trashinfo` record.
+    pub fn put(&self, path: &Path) -> anyhow::Result<(PathBuf, PathBuf)> {
+        let original = std::path::absolute(path)?;
+        let files_dir = self.files_dir();
+        let info_dir = self.info_dir();
+        std::fs::create_dir_all(&files_dir)?;
+        std::fs::create_dir_all(&info_dir)?;
+
+        let file_name = path.file_name().context("Cannot trash a path without a file name")?;
+        let stem = Path::new(file_name).file_stem().unwrap_or(file_name);
+        let ext = Path::new(file_name).extension();
+
+        for i in 0..1000 {
+            // Built as OsString so names that are not UTF-8 survive unchanged
+            let mut name = OsString::new();
+            if i == 0 {
+                name.push(file_name);
+            } else {
+                name.push(stem);
+                name.push(format!(" ({})", i));
+                if let Some(ext) = ext {
+                    name.push(".");
+                    name.push(ext);
+                }
+            }
+            let mut info_name = name.clone();
+            info_name.push(".trashinfo");
+            let info_path = info_dir.join(info_name);
+            let trashed_path = files_dir.join(&name);
+            if trashed_path.symlink_metadata().is_ok() {
+                continue;
+            }
+
+            // Creating the info file exclusively reserves the name (as the spec requires)
+            let mut info = match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to create {:?}", info_path)),
+            };
+            write!(
+                info,
+                "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                encode_path(&original),
+                Local::now().format("%Y-%m-%dT%H:%M:%S")
+            )?;
+            info.sync_all()?;
+
+            if let Err(e) = move_file(path, &trashed_path, &CopyOptions::new()) {
+                let _ = std::fs::remove_file(&info_path);
+                return Err(e).with_context(|| format!("Failed to move {:?} to the trash", path));
+            }
+            return Ok((trashed_path, info_path));
+        }
+        anyhow::bail!("Too many trashed files named {:?}", file_name)
+    }
+
+    /// The most recent entry trashed from `original` no earlier than `since`,
+    /// as the trashed file and its `.trashinfo` record.
+    pub fn find(&self, original: &Path, since: NaiveDateTime) -> Option<(PathBuf, PathBuf)> {
+        let wanted = format!("Path={}", encode_path(&std::path::absolute(original).ok()?));
+        let mut best: Option<(NaiveDateTime, PathBuf, PathBuf)> = None;
+        for entry in std::fs::read_dir(self.info_dir()).ok()?.flatten() {
+            let info_path = entry.path();
+            if info_path.extension().is_none_or(|ext| ext != "trashinfo") {
+                continue;
+            }
+            let Some(name) = info_path.file_stem() else {
+                continue;
+            };
+            let Ok(content) = std::fs::read_to_string(&info_path) else {
+                continue;
+            };
+            if !content.lines().any(|line| line == wanted) {
+                continue;
+            }
+            let deleted = content
+                .lines()
+                .find_map(|line| line.strip_prefix("DeletionDate="))
+                .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").ok());
+            let trashed_path = self.files_dir().join(name);
+            let Some(deleted) = deleted.filter(|deleted| *deleted >= since) else {
+                continue;
+            };
+            if trashed_path.exists() && best.as_ref().is_none_or(|(newest, _, _)| deleted > *newest) {
+                best = Some((deleted, trashed_path, info_path));
+            }
+        }
+        best.map(|(_, trashed_path, info_path)| (trashed_path, info_path))
+    }
+}
+
+/// Puts a trashed file back at `original` and drops its `.trashinfo` record.
+pub fn restore(trashed_path: &Path, info_path: &Path, original: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = original.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    move_file(trashed_path, original, &CopyOptions::new())
+        .with_context(|| format!("Failed to restore {:?} from the trash", original))?;
+    if info_path.exists() {
+        std::fs::remove_file(info_path)?;
+    }
+    Ok(())
+}
+
+/// Percent-encodes a path for the `Path=` key, keeping `/` and unreserved characters.
+/// The raw bytes are encoded, so a name that is not valid UTF-8 is kept intact.
+pub(crate) fn encode_path(path: &Path) -> String {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+
+    let mut encoded = String::new();
+    for byte in bytes {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
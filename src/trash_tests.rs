@@ -0,0 +1,55 @@
+#[cfg(test)]
+mod tests {
+    use crate::trash::{self, Trash};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trash_put_and_restore() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::with_root(dir.path().join("Trash"));
+        let first = dir.path().join("my file.txt");
+        fs::write(&first, "one").unwrap();
+
+        let (trashed, info) = trash.put(&first).unwrap();
+        assert!(!first.exists());
+        assert_eq!(trashed, trash.files_dir().join("my file.txt"));
+        let record = fs::read_to_string(&info).unwrap();
+        assert!(record.starts_with("[Trash Info]\n"));
+        assert!(record.contains("my%20file.txt"));
+        assert!(record.contains("DeletionDate="));
+
+        // A second file with the same name gets a fresh slot
+        fs::write(&first, "two").unwrap();
+        let (second, _) = trash.put(&first).unwrap();
+        assert_eq!(second, trash.files_dir().join("my file (1).txt"));
+
+        trash::restore(&trashed, &info, &first).unwrap();
+        assert_eq!(fs::read_to_string(&first).unwrap(), "one");
+        assert!(!info.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_trash_keeps_names_that_are_not_utf8() {
+        use crate::trash::encode_path;
+        use chrono::Local;
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::path::Path;
+
+        assert_eq!(encode_path(Path::new(OsStr::from_bytes(b"/tmp/caf\xe9 1.txt"))), "/tmp/caf%E9%201.txt");
+
+        let dir = tempdir().unwrap();
+        let trash = Trash::with_root(dir.path().join("Trash"));
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        let original = dir.path().join(name);
+        if fs::write(&original, "latin-1").is_err() {
+            return; // The file system only accepts UTF-8 names
+        }
+        let started = Local::now().naive_local() - chrono::Duration::seconds(2);
+        let (trashed, info) = trash.put(&original).unwrap();
+        assert_eq!(trashed, trash.files_dir().join(name));
+        assert_eq!(trash.find(&original, started), Some((trashed, info)));
+    }
+}
@@ -3,6 +3,8 @@ use crate::config::Config;
 #[cfg(feature = "ui")]
 use crate::engine::Engine;
 #[cfg(feature = "ui")]
+use crate::journal::JournalStore;
+#[cfg(feature = "ui")]
 use crossterm::{
     event::{self, Event, KeyCode},
     execute,
@@ -30,6 +32,13 @@ pub fn run_ui(path: PathBuf) -> anyhow::Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     let mut logs: Vec<String> = vec!["Ready to organize.".to_string()];
+    let journal_store = match JournalStore::open_default() {
+        Ok(store) => Some(store),
+        Err(e) => {
+            logs.push(format!("Error: {:#}", e));
+            None
+        }
+    };
     let mut progress: u16 = 0;
 
     loop {
@@ -89,10 +98,20 @@ pub fn run_ui(path: PathBuf) -> anyhow::Result<()> {
                         
                         // Use config for UI
                         if let Ok(config) = Config::from_file(PathBuf::from("rarch.toml")) {
-                            let engine = Engine::new(config, path.clone());
+                            let engine = match Engine::new(config, path.clone()) {
+                                Ok(engine) => engine,
+                                Err(e) => {
+                                    logs.push(format!("Error: {:#}", e));
+                                    continue;
+                                }
+                            };
                             
                             logs.push("Executing reorganization...".to_string());
-                            let run_result = engine.execute(Some(PathBuf::from("rarch_journal.json")), |pos, total, _msg| {
+                            let session = journal_store.as_ref().map(|store| store.begin("ui", &path));
+                            if session.is_none() {
+                                logs.push("Error: No journal available; this run cannot be undone".to_string());
+                            }
+                            let run_result = engine.execute(session.as_ref(), |pos, total, _msg| {
                                 progress = ((pos as f32 / total as f32) * 100.0) as u16;
                                 // We can't easily push to logs here because terminal.draw is blocking
                                 // but for a simple UI it's fine for now if we don't redraw mid-loop
@@ -106,7 +125,6 @@ pub fn run_ui(path: PathBuf) -> anyhow::Result<()> {
                                     for op in journal.operations.iter().take(5) {
                                         logs.push(format!("Moved: {:?}", op.from.file_name().unwrap()));
                                     }
-                                    let _ = journal.save(PathBuf::from("rarch_journal.json"));
                                 }
                                 Err(e) => {
                                     logs.push(format!("Error: {}", e));
@@ -0,0 +1,567 @@
+use crate::archive;
+use crate::dedup;
+use crate::engine::{same_file, temp_sibling, Engine};
+use crate::journal::{Fingerprint, JournalStore, OpType, Operation, Session, SessionLog};
+use crate::trash;
+use anyhow::Context;
+use fs_extra::file::{move_file, CopyOptions};
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Reverts one journaled operation. Returns `false` if there was nothing left
+/// to revert, e.g. because it was already undone.
+///
+/// Files are never written over an existing file at the original location
+/// unless `force` is set; restored copies are verified against their source.
+/// Created copies, links and extracted files are only removed while their
+/// source still exists and they match `fingerprint`, the journaled state.
+pub fn revert(op: &Operation, fingerprint: Option<&Fingerprint>, force: bool) -> anyhow::Result<bool> {
+    match &op.op_type {
+        // `to` holds the file itself (a reflink clone shares no data that could change)
+        OpType::Move | OpType::Reflink(_) => {
+            if !op.to.exists() {
+                return Ok(false);
+            }
+            make_room(&op.from, force)?;
+            move_file(&op.to, &op.from, &CopyOptions::new()).with_context(|| format!("Failed to move {:?} back", op.to))?;
+            Ok(true)
+        }
+        // `to` shares its data with the original; the duplicate gets its own copy back
+        OpType::HardLink(_) => {
+            if !op.to.exists() {
+                return Ok(false);
+            }
+            if !restore_copy(&op.to, &op.from, force)? {
+                return Ok(false);
+            }
+            std::fs::remove_file(&op.to).with_context(|| format!("Restored {:?} but failed to remove {:?}", op.from, op.to))?;
+            Ok(true)
+        }
+        // Recreated from the original, which is still in place as operations are undone in reverse
+        OpType::SoftLink(original) | OpType::Deleted(original) => {
+            anyhow::ensure!(original.is_file(), "Cannot recreate {:?}: the original {:?} is gone", op.from, original);
+            if let (OpType::SoftLink(_), Some(detail), false) = (&op.op_type, replaced_link(&op.to, original), force) {
+                anyhow::bail!("{} (use --force to remove it anyway)", detail);
+            }
+            let restored = restore_copy(original, &op.from, force)?;
+            if matches!(op.op_type, OpType::SoftLink(_)) && op.to.is_symlink() {
+                std::fs::remove_file(&op.to)?;
+            }
+            Ok(restored)
+        }
+        // A directory the extraction created goes once its files are gone, unless something else was put in it
+        OpType::Extract if op.to.is_dir() => match std::fs::remove_dir(&op.to) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("Failed to remove {:?}", op.to)),
+        },
+        // The source never left; only the created copy, link or extracted file is removed
+        OpType::Copy | OpType::Link | OpType::Extract => {
+            if !op.to.is_file() {
+                return Ok(false);
+            }
+            if !force {
+                if let Some(check) = keep_result(op, fingerprint, false)? {
+                    anyhow::bail!("{}", check.detail());
+                }
+            }
+            std::fs::remove_file(&op.to)?;
+            Ok(true)
+        }
+        OpType::Symlink => {
+            if !op.to.is_symlink() {
+                return Ok(false);
+            }
+            std::fs::remove_file(&op.to)?;
+            Ok(true)
+        }
+        // Give the file its own copy of the data again
+        OpType::TwinLink => {
+            if !op.to.is_file() || !same_file(&op.from, &op.to) {
+                // Already independent, or replaced by something else since
+                return Ok(false);
+            }
+            let tmp = temp_sibling(&op.from, "rarch-undo");
+            copy_verified(&op.to, &tmp)?;
+            std::fs::rename(&tmp, &op.from)?;
+            Ok(true)
+        }
+        OpType::Trash(info_path) => {
+            if !op.to.exists() {
+                return Ok(false);
+            }
+            make_room(&op.from, force)?;
+            trash::restore(&op.to, info_path, &op.from)?;
+            Ok(true)
+        }
+        // The member stays in the archive; only the file is brought back
+        OpType::Archive { archive, member } => {
+            let tmp = temp_sibling(&op.from, "rarch-undo");
+            archive::extract_member(archive, member, &tmp)?;
+            if op.from.exists() {
+                if dedup::same_content(&tmp, &op.from)? {
+                    std::fs::remove_file(&tmp)?;
+                    return Ok(false);
+                }
+                if !force {
+                    std::fs::remove_file(&tmp)?;
+                    anyhow::bail!("{:?} already exists (use --force to overwrite it)", op.from);
+                }
+            }
+            std::fs::rename(&tmp, &op.from)?;
+            Ok(true)
+        }
+        OpType::AlreadyPresent => Ok(false),
+    }
+}
+
+/// Selects the operations `rarch undo` reverts. Every given criterion must
+/// match; an empty filter selects everything.
+#[derive(Default)]
+pub struct UndoFilter {
+    /// Names of the rules that produced the operation
+    pub rules: Vec<String>,
+    /// Directory the operation's result was placed in (absolute)
+    pub target: Option<PathBuf>,
+    /// Glob on the original file name
+    pub name: Option<GlobMatcher>,
+    /// Ranges of 1-based positions in the session, as listed by `rarch history --session`
+    pub indices: Option<Vec<RangeInclusive<usize>>>,
+}
+
+impl UndoFilter {
+    pub fn new(rules: Vec<String>, target: Option<PathBuf>, name: Option<&str>, indices: Option<&str>) -> anyhow::Result<Self> {
+        let name = match name {
+            Some(pattern) => Some(
+                GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .with_context(|| format!("Invalid glob {:?}", pattern))?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            rules,
+            target: target.map(|dir| std::path::absolute(&dir).unwrap_or(dir)),
+            name,
+            indices: indices.map(parse_indices).transpose()?,
+        })
+    }
+
+    /// Positions (0-based) of the operations to undo. Deduplicated files
+    /// depend on their original, so an original that is moved back takes the
+    /// duplicates pointing at it along. Fails if a requested position is not
+    /// part of `operations`.
+    pub fn select(&self, operations: &[Operation]) -> anyhow::Result<BTreeSet<usize>> {
+        if let Some(last) = self.indices.iter().flatten().map(|range| *range.end()).max() {
+            anyhow::ensure!(
+                last <= operations.len(),
+                "There is no operation {} (the session has {}; see 'rarch history --session')",
+                last,
+                operations.len()
+            );
+        }
+        let mut selected: BTreeSet<usize> =
+            (0..operations.len()).filter(|&index| self.matches(index + 1, &operations[index])).collect();
+        let originals: HashSet<&Path> = selected
+            .iter()
+            .map(|&index| &operations[index])
+            .filter(|op| op.op_type == OpType::Move)
+            .map(|op| op.to.as_path())
+            .collect();
+        selected.extend(
+            (0..operations.len())
+                .filter(|&index| operations[index].op_type.duplicate_of().is_some_and(|original| originals.contains(original))),
+        );
+        Ok(selected)
+    }
+
+    /// True if the operation at 1-based position `index` is selected on its own.
+    pub fn matches(&self, index: usize, op: &Operation) -> bool {
+        if !self.rules.is_empty() && !op.rule_name.as_ref().is_some_and(|rule| self.rules.contains(rule)) {
+            return false;
+        }
+        if let Some(target) = &self.target {
+            if !std::path::absolute(&op.to).is_ok_and(|to| to.starts_with(target)) {
+                return false;
+            }
+        }
+        if let Some(name) = &self.name {
+            if !op.from.file_name().is_some_and(|file_name| name.is_match(file_name)) {
+                return false;
+            }
+        }
+        self.indices.as_ref().is_none_or(|ranges| ranges.iter().any(|range| range.contains(&index)))
+    }
+}
+
+/// Parses a list of positions such as `"3,5-7"`.
+pub fn parse_indices(list: &str) -> anyhow::Result<Vec<RangeInclusive<usize>>> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .with_context(|| format!("Invalid operation number {:?} (expected e.g. \"3,5-7\")", n))
+    };
+    let mut ranges = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let range = match part.split_once('-') {
+            Some((start, end)) => parse(start)?..=parse(end)?,
+            None => parse(part)?..=parse(part)?,
+        };
+        anyhow::ensure!(!range.is_empty(), "Invalid range {:?}: it ends before it starts", part);
+        ranges.push(range);
+    }
+    Ok(ranges)
+}
+
+/// Outcome of undoing one session.
+#[derive(Default)]
+pub struct UndoSummary {
+    /// Operations the filter selected that were still in effect
+    pub selected: usize,
+    pub reverted: usize,
+    /// Operations that had nothing left to revert
+    pub unchanged: usize,
+    /// Operations that could not be reverted, with the reason; they stay in effect
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl UndoSummary {
+    fn add(&mut self, op: &Operation, result: anyhow::Result<bool>) -> bool {
+        match result {
+            Ok(true) => self.reverted += 1,
+            Ok(false) => self.unchanged += 1,
+            Err(e) => {
+                self.failed.push((op.from.clone(), format!("{:#}", e)));
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Reverts the selected operations of a legacy journal in reverse order,
+/// carrying on past the ones that fail.
+pub fn revert_all(operations: &[Operation], filter: &UndoFilter, force: bool) -> anyhow::Result<UndoSummary> {
+    let mut summary = UndoSummary::default();
+    for index in filter.select(operations)?.into_iter().rev() {
+        let op = &operations[index];
+        summary.selected += 1;
+        summary.add(op, revert(op, None, force));
+    }
+    Ok(summary)
+}
+
+/// Reverts the selected operations of a session in reverse order, carrying on
+/// past the ones that fail. Each reverted one is marked in the journal right
+/// away, so it is never replayed, and the session counts as undone once
+/// nothing of it is left. The reverted operations are journaled as an undo
+/// session for `rarch redo`.
+pub fn undo_session(store: &JournalStore, log: &SessionLog, filter: &UndoFilter, force: bool) -> anyhow::Result<UndoSummary> {
+    let mut summary = UndoSummary::default();
+    let mut left = log.remaining();
+    let undo = store.begin_undo(&log.info);
+    for index in filter.select(&log.operations)?.into_iter().rev() {
+        let op = &log.operations[index];
+        if log.reverted.contains(&index) {
+            continue;
+        }
+        summary.selected += 1;
+        let result = revert(op, log.fingerprints.get(index).and_then(Option::as_ref), force);
+        let reverted = matches!(result, Ok(true));
+        if !summary.add(op, result) {
+            continue;
+        }
+        if reverted {
+            undo.record_done(std::slice::from_ref(op))?;
+        }
+        store.mark_reverted(&log.info.id, index)?;
+        left -= 1;
+    }
+    if left == 0 && summary.selected > 0 {
+        store.mark_undone(&log.info.id)?;
+    }
+    Ok(summary)
+}
+
+/// What undoing an operation would do, as reported by `rarch undo --dry-run`.
+pub enum Check {
+    /// Would be reverted as journaled
+    Restore,
+    /// Nothing left to revert, e.g. because it was reverted by hand
+    Unchanged(String),
+    /// The file undo works on is gone
+    Missing(String),
+    /// The file changed since it was journaled
+    Modified(String),
+    /// Something else now occupies the original location
+    Collision(String),
+}
+
+impl Check {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Check::Restore => "restore",
+            Check::Unchanged(_) => "nothing to do",
+            Check::Missing(_) => "missing",
+            Check::Modified(_) => "modified",
+            Check::Collision(_) => "collision",
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            Check::Restore => "",
+            Check::Unchanged(detail) | Check::Missing(detail) | Check::Modified(detail) | Check::Collision(detail) => detail,
+        }
+    }
+}
+
+/// Looks at what `revert` would find without changing anything. `fingerprint`
+/// is the journaled state of the operation's result, if one was taken.
+pub fn inspect(op: &Operation, fingerprint: Option<&Fingerprint>, force: bool) -> anyhow::Result<Check> {
+    let collision = |path: &Path| {
+        Check::Collision(match force {
+            true => format!("{:?} exists and would be overwritten", path),
+            false => format!("{:?} exists (use --force to overwrite it)", path),
+        })
+    };
+    let changed = |path: &Path| modified(path, fingerprint);
+    let occupied = |path: &Path| path.symlink_metadata().is_ok();
+
+    let check = match &op.op_type {
+        OpType::Move | OpType::Reflink(_) | OpType::Trash(_) | OpType::HardLink(_) if !op.to.exists() => {
+            Check::Missing(format!("{:?} is gone", op.to))
+        }
+        OpType::HardLink(_) if op.from.is_file() && !same_file(&op.from, &op.to) && dedup::same_content(&op.from, &op.to)? => {
+            Check::Unchanged(format!("{:?} is already back", op.from))
+        }
+        OpType::Move | OpType::Reflink(_) | OpType::Trash(_) | OpType::HardLink(_) if occupied(&op.from) => collision(&op.from),
+        OpType::Move | OpType::Reflink(_) | OpType::Trash(_) | OpType::HardLink(_) => changed(&op.to)?.unwrap_or(Check::Restore),
+        OpType::SoftLink(original) | OpType::Deleted(original) if !original.is_file() => {
+            Check::Missing(format!("the original {:?} is gone", original))
+        }
+        OpType::SoftLink(original) if replaced_link(&op.to, original).is_some() => {
+            let detail = replaced_link(&op.to, original).unwrap_or_default();
+            Check::Modified(match force {
+                true => detail + "; it would be removed anyway",
+                false => detail + " (use --force to remove it anyway)",
+            })
+        }
+        OpType::SoftLink(original) | OpType::Deleted(original)
+            if op.from.is_file() && !same_file(original, &op.from) && dedup::same_content(original, &op.from)? =>
+        {
+            Check::Unchanged(format!("{:?} is already back", op.from))
+        }
+        OpType::SoftLink(_) | OpType::Deleted(_) if occupied(&op.from) => collision(&op.from),
+        OpType::SoftLink(_) | OpType::Deleted(_) => Check::Restore,
+        OpType::Extract if op.to.is_dir() => Check::Restore,
+        OpType::Copy | OpType::Link | OpType::Extract if !op.to.is_file() => Check::Missing(format!("{:?} is gone", op.to)),
+        OpType::Copy | OpType::Link | OpType::Extract => keep_result(op, fingerprint, force)?.unwrap_or(Check::Restore),
+        OpType::Symlink if !op.to.is_symlink() => Check::Missing(format!("the link {:?} is gone", op.to)),
+        OpType::Symlink => Check::Restore,
+        OpType::TwinLink if !op.to.is_file() || !same_file(&op.from, &op.to) => {
+            Check::Unchanged(format!("{:?} no longer shares its data with {:?}", op.from, op.to))
+        }
+        OpType::TwinLink => changed(&op.from)?.unwrap_or(Check::Restore),
+        OpType::Archive { archive, .. } if !archive.is_file() => Check::Missing(format!("the archive {:?} is gone", archive)),
+        OpType::Archive { archive, member } if !archive::members(archive)?.contains(member) => {
+            Check::Missing(format!("{:?} no longer holds {:?}", archive, member))
+        }
+        OpType::Archive { .. } if occupied(&op.from) => collision(&op.from),
+        OpType::Archive { .. } => Check::Restore,
+        OpType::AlreadyPresent => Check::Unchanged(String::new()),
+    };
+    Ok(check)
+}
+
+/// Compares `path` with its journaled fingerprint, if one was taken. With a
+/// content hash the content decides, so a file that was only touched counts
+/// as unchanged.
+fn modified(path: &Path, fingerprint: Option<&Fingerprint>) -> anyhow::Result<Option<Check>> {
+    let (Some(journaled), Some(now)) = (fingerprint, Fingerprint::of(path)) else {
+        return Ok(None);
+    };
+    if now.size != journaled.size {
+        return Ok(Some(Check::Modified(format!(
+            "{:?} is {} bytes, was {} when journaled",
+            path, now.size, journaled.size
+        ))));
+    }
+    let changed = match &journaled.hash {
+        Some(hash) => dedup::hash_file(path).with_context(|| format!("Failed to read {:?}", path))? != *hash,
+        None => now.mtime != journaled.mtime,
+    };
+    Ok(changed.then(|| Check::Modified(format!("{:?} was modified since it was journaled", path))))
+}
+
+/// Why the copy, link or extracted file at `op.to` must not be removed: its
+/// source is gone, so it may be the only copy left, or it changed since it
+/// was journaled. The detail says what `force` would do about it.
+fn keep_result(op: &Operation, fingerprint: Option<&Fingerprint>, force: bool) -> anyhow::Result<Option<Check>> {
+    let check = if op.from.symlink_metadata().is_err() {
+        Check::Missing(format!("{:?} is gone, so {:?} may be the only copy left", op.from, op.to))
+    } else if let Some(check) = modified(&op.to, fingerprint)? {
+        check
+    } else if op.op_type == OpType::Link && !same_file(&op.from, &op.to) {
+        Check::Modified(format!("{:?} no longer shares its data with {:?}", op.to, op.from))
+    } else if op.op_type == OpType::Copy && !dedup::same_content(&op.from, &op.to)? {
+        Check::Modified(format!("{:?} was edited since it was copied", op.to))
+    } else {
+        return Ok(None);
+    };
+    let suffix = match force {
+        true => "; it would be removed anyway",
+        false => " (use --force to remove it anyway)",
+    };
+    Ok(Some(match check {
+        Check::Missing(detail) => Check::Missing(detail + suffix),
+        Check::Modified(detail) => Check::Modified(detail + suffix),
+        other => other,
+    }))
+}
+
+/// Says where the symbolic link at `link` points instead, if it was replaced
+/// by one that no longer leads to `original`.
+fn replaced_link(link: &Path, original: &Path) -> Option<String> {
+    let target = std::fs::read_link(link).ok()?;
+    let resolved = link.parent().map_or_else(|| target.clone(), |dir| dir.join(&target));
+    let leads_to_original = target == original
+        || matches!((resolved.canonicalize(), original.canonicalize()), (Ok(a), Ok(b)) if a == b);
+    (!leads_to_original).then(|| format!("{:?} now points at {:?} instead of {:?}", link, target, original))
+}
+
+/// Fails if something already occupies `path`, or removes it when forced.
+fn make_room(path: &Path, force: bool) -> anyhow::Result<()> {
+    if path.symlink_metadata().is_err() {
+        return Ok(());
+    }
+    anyhow::ensure!(force, "{:?} already exists (use --force to overwrite it)", path);
+    std::fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))
+}
+
+/// Copies `source` to `dest` as an independent file. Returns `false` if
+/// `dest` already holds the same content.
+fn restore_copy(source: &Path, dest: &Path, force: bool) -> anyhow::Result<bool> {
+    if dest.is_file() && !same_file(source, dest) && dedup::same_content(source, dest)? {
+        return Ok(false);
+    }
+    make_room(dest, force)?;
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = temp_sibling(dest, "rarch-undo");
+    copy_verified(source, &tmp)?;
+    std::fs::rename(&tmp, dest)?;
+    Ok(true)
+}
+
+/// Copies a file and checks the content hash of the copy against the source.
+fn copy_verified(source: &Path, dest: &Path) -> anyhow::Result<()> {
+    let expected = dedup::hash_file(source).with_context(|| format!("Failed to read {:?}", source))?;
+    std::fs::copy(source, dest).with_context(|| format!("Failed to copy {:?} to {:?}", source, dest))?;
+    let actual = dedup::hash_file(dest)?;
+    if actual != expected {
+        let _ = std::fs::remove_file(dest);
+        anyhow::bail!("Copy of {:?} does not match the original", source);
+    }
+    Ok(())
+}
+
+/// Outcome of redoing one undo.
+pub struct RedoSummary {
+    pub redone: usize,
+    /// Operations that were skipped or failed, with the reason
+    pub problems: Vec<String>,
+}
+
+/// Fails if the undo `log` reverted something `redo_session` cannot reapply.
+/// Extracted files can only be recreated by extracting their archive again,
+/// which would not give back the same files, so such an undo is not redone
+/// at all rather than in part.
+pub fn ensure_redoable(log: &SessionLog) -> anyhow::Result<()> {
+    let extracted = log
+        .operations
+        .iter()
+        .enumerate()
+        .find(|(index, op)| !log.reverted.contains(index) && op.op_type == OpType::Extract);
+    if let Some((_, op)) = extracted {
+        anyhow::bail!(
+            "session {} undid the extraction of {:?}, which cannot be redone; extract it again with 'rarch run'",
+            log.info.id,
+            op.from
+        );
+    }
+    Ok(())
+}
+
+/// Reapplies what the undo `log` reverted, in the original order. Clashes at
+/// the targets are resolved by `engine` with the same conflict strategies as
+/// a run. The reapplied operations are journaled to `session`, which can be
+/// undone like any other; each one is marked as redone in the undo's log.
+pub fn redo_session(store: &JournalStore, engine: &Engine, log: &SessionLog, session: &Session) -> anyhow::Result<RedoSummary> {
+    ensure_redoable(log)?;
+    let mut summary = RedoSummary { redone: 0, problems: Vec::new() };
+    let mut left = log.remaining();
+    // Where moved files ended up this time, for the duplicates linked to them
+    let mut placed: HashMap<PathBuf, PathBuf> = HashMap::new();
+    // The undo reverted newest first
+    for (index, op) in log.operations.iter().enumerate().rev() {
+        if log.reverted.contains(&index) {
+            continue;
+        }
+        let mut planned = op.clone();
+        match &mut planned.op_type {
+            OpType::Trash(info_path) => *info_path = PathBuf::new(),
+            OpType::Archive { archive, member } => {
+                planned.to = std::mem::take(archive);
+                member.clear();
+            }
+            OpType::HardLink(original) | OpType::Reflink(original) | OpType::SoftLink(original) | OpType::Deleted(original) => {
+                if let Some(final_original) = placed.get(original.as_path()) {
+                    *original = final_original.clone();
+                }
+                if let OpType::Deleted(original) = &planned.op_type {
+                    planned.to = original.clone();
+                }
+            }
+            _ => {}
+        }
+        if planned.from.symlink_metadata().is_err() {
+            summary.problems.push(format!("{:?}: no longer exists", planned.from));
+            continue;
+        }
+
+        if let Some(parent) = planned.to.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                summary.problems.push(format!("{:?}: cannot create {:?}: {}", planned.from, parent, e));
+                continue;
+            }
+        }
+        let result = engine.handle_conflict(&planned).and_then(|final_to| match final_to {
+            Some(final_to) => engine.apply_journaled(&planned, &final_to, Some(session)).map(|_| Some(final_to)),
+            None => Ok(None),
+        });
+        match result {
+            Ok(Some(final_to)) => {
+                if planned.op_type == OpType::Move {
+                    placed.insert(planned.to.clone(), final_to);
+                }
+                store.mark_reverted(&log.info.id, index)?;
+                summary.redone += 1;
+                left -= 1;
+            }
+            Ok(None) => summary.problems.push(format!("{:?}: skipped, {:?} already exists", planned.from, planned.to)),
+            Err(e) => summary.problems.push(format!("{:?}: {:#}", planned.from, e)),
+        }
+    }
+    if left == 0 {
+        store.mark_undone(&log.info.id)?;
+    }
+    Ok(summary)
+}
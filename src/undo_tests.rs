@@ -0,0 +1,598 @@
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::engine::{same_file, Engine};
+    use crate::journal::{JournalStore, OpType, Operation};
+    use crate::recover::{settle, Outcome};
+    use crate::trash::Trash;
+    use crate::undo::{inspect, parse_indices, redo_session, revert, undo_session, Check, UndoFilter};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn text_rule() -> Config {
+        toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Text"
+            extensions = ["txt"]
+            target = "texts"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_hard_link_restores_an_independent_copy() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "same").unwrap();
+        fs::write(dir.path().join("b.txt"), "same").unwrap();
+        let engine = Engine::new(text_rule(), dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        assert!(matches!(journal.operations[1].op_type, OpType::HardLink(_)));
+
+        for op in journal.operations.iter().rev() {
+            assert!(revert(op, None, false).unwrap());
+        }
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        assert_eq!(fs::read_to_string(&b).unwrap(), "same");
+        assert!(!same_file(&a, &b));
+        assert!(!dir.path().join("texts/b.txt").exists());
+
+        // Undoing again finds nothing left to do
+        for op in journal.operations.iter().rev() {
+            assert!(!revert(op, None, false).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_undo_refuses_to_overwrite_without_force() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "organized").unwrap();
+        let engine = Engine::new(text_rule(), dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let op = &journal.operations[0];
+
+        // A new file took the old name in the meantime
+        fs::write(dir.path().join("a.txt"), "newer").unwrap();
+        let err = revert(op, None, false).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "newer");
+        assert!(dir.path().join("texts/a.txt").exists());
+
+        assert!(revert(op, None, true).unwrap());
+        assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "organized");
+    }
+
+    #[test]
+    fn test_undo_keeps_modified_copies() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "draft").unwrap();
+        let mut config = text_rule();
+        config.rules[0].action = Some(crate::config::Action::Copy);
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let op = &journal.operations[0];
+
+        fs::write(&op.to, "edited copy").unwrap();
+        assert!(revert(op, None, false).is_err());
+        assert!(op.to.exists());
+        assert!(revert(op, None, true).unwrap());
+        assert!(!op.to.exists());
+    }
+
+    #[test]
+    fn test_undo_restores_trashed_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "discard me").unwrap();
+        let trash_dir = tempdir().unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        let mut config = text_rule();
+        config.rules[0].action = Some(crate::config::Action::Trash);
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap().with_trash(Trash::with_root(trash_dir.path().to_path_buf()));
+        engine.execute(Some(&session), |_, _, _| {}).unwrap();
+
+        let log = store.load(&session.info.id).unwrap();
+        let op = &log.operations[0];
+        let OpType::Trash(info_path) = &op.op_type else { panic!("expected a trash operation") };
+        assert!(info_path.starts_with(trash_dir.path()) && info_path.exists());
+        assert!(!dir.path().join("a.txt").exists());
+
+        let summary = undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "discard me");
+        assert!(!op.to.exists());
+        assert!(!info_path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_keeps_results_that_may_be_the_only_copy() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "alpha").unwrap();
+        fs::write(dir.path().join("b.log"), "beta").unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Copies"
+            extensions = ["txt"]
+            action = "copy"
+            target = "copies"
+
+            [[rules]]
+            name = "Links"
+            extensions = ["log"]
+            action = "hardlink"
+            target = "links"
+            "#,
+        )
+        .unwrap();
+        Engine::new(config, dir.path().to_path_buf()).unwrap().execute(Some(&session), |_, _, _| {}).unwrap();
+        let log = store.load(&session.info.id).unwrap();
+        let find = |kind: OpType| log.operations.iter().find(|op| op.op_type == kind).unwrap();
+        let (copy, link) = (find(OpType::Copy), find(OpType::Link));
+
+        // The source of the copy was deleted by the user, and the linked file edited through either name
+        fs::remove_file(&copy.from).unwrap();
+        fs::write(&link.from, "edited").unwrap();
+
+        let summary = undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert_eq!(summary.failed.len(), 2);
+        assert!(summary.failed.iter().all(|(_, reason)| reason.contains("--force")), "{:?}", summary.failed);
+        assert_eq!(fs::read_to_string(&copy.to).unwrap(), "alpha");
+        assert_eq!(fs::read_to_string(&link.to).unwrap(), "edited");
+        for (op, fingerprint) in log.operations.iter().zip(&log.fingerprints) {
+            let check = inspect(op, fingerprint.as_ref(), false).unwrap();
+            assert!(matches!(check, Check::Missing(_) | Check::Modified(_)), "{}", check.label());
+        }
+
+        let summary = undo_session(&store, &log, &UndoFilter::default(), true).unwrap();
+        assert!(summary.failed.is_empty());
+        assert!(!copy.to.exists() && !link.to.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_compares_the_content_hash_of_links() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.log"), "alpha").unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Links"
+            extensions = ["log"]
+            action = "hardlink"
+            target = "links"
+            "#,
+        )
+        .unwrap();
+        Engine::new(config, dir.path().to_path_buf()).unwrap().execute(Some(&session), |_, _, _| {}).unwrap();
+        let log = store.load(&session.info.id).unwrap();
+        let (op, fingerprint) = (&log.operations[0], log.fingerprints[0].as_ref());
+        assert!(fingerprint.is_some_and(|f| f.hash.is_some()));
+
+        // Only touched: the content is what was journaled
+        let touch = |content: &str| {
+            let mtime = fs::metadata(&op.to).unwrap().modified().unwrap();
+            fs::write(&op.from, content).unwrap();
+            fs::File::options().write(true).open(&op.to).unwrap().set_modified(mtime + std::time::Duration::from_secs(1)).unwrap();
+        };
+        touch("alpha");
+        assert!(matches!(inspect(op, fingerprint, false).unwrap(), Check::Restore));
+
+        // Same size, but different bytes
+        touch("omega");
+        assert!(matches!(inspect(op, fingerprint, false).unwrap(), Check::Modified(_)));
+        assert!(revert(op, fingerprint, false).is_err());
+        assert!(op.to.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_keeps_a_symlink_that_was_repointed() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "same").unwrap();
+        fs::write(dir.path().join("b.txt"), "same").unwrap();
+        fs::write(dir.path().join("mine.dat"), "mine").unwrap();
+        let mut config = text_rule();
+        config.dedup = crate::config::DedupMode::Symlink;
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        let journal = engine.execute(None, |_, _, _| {}).unwrap();
+        let op = &journal.operations[1];
+        assert!(matches!(op.op_type, OpType::SoftLink(_)));
+
+        // The user pointed the link at a file of their own
+        let mine = dir.path().join("mine.dat");
+        fs::remove_file(&op.to).unwrap();
+        std::os::unix::fs::symlink(&mine, &op.to).unwrap();
+        assert!(matches!(inspect(op, None, false).unwrap(), Check::Modified(_)));
+        let err = revert(op, None, false).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert_eq!(fs::read_link(&op.to).unwrap(), mine);
+        assert!(!op.from.exists());
+
+        assert!(revert(op, None, true).unwrap());
+        assert!(!op.to.is_symlink());
+        assert_eq!(fs::read_to_string(&op.from).unwrap(), "same");
+        assert_eq!(fs::read_to_string(&mine).unwrap(), "mine");
+    }
+
+    #[test]
+    fn test_sessions_are_kept_apart_and_undone_separately() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let engine = Engine::new(text_rule(), dir.path().to_path_buf()).unwrap();
+        let first = store.begin("run", dir.path());
+        engine.execute(Some(&first), |_, _, _| {}).unwrap();
+
+        fs::write(dir.path().join("b.txt"), "b").unwrap();
+        let second = store.begin("run", dir.path());
+        let engine = Engine::new(text_rule(), dir.path().to_path_buf()).unwrap();
+        engine.execute(Some(&second), |_, _, _| {}).unwrap();
+        // A session that did nothing leaves no log
+        store.begin("run", dir.path());
+
+        let sessions = store.sessions().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].info.id, first.info.id);
+        assert_eq!(sessions[0].operations[0].from, dir.path().join("a.txt"));
+        assert_eq!(sessions[1].operations[0].from, dir.path().join("b.txt"));
+
+        let log = store.load(&first.info.id).unwrap();
+        for op in log.operations.iter().rev() {
+            revert(op, None, false).unwrap();
+        }
+        store.mark_undone(&log.info.id).unwrap();
+        assert!(dir.path().join("a.txt").exists());
+        assert!(dir.path().join("texts/b.txt").exists());
+        let sessions = store.sessions().unwrap();
+        assert!(sessions[0].undone.is_some());
+        assert!(sessions[1].undone.is_none());
+        assert!(store.load("nope").is_err());
+
+        // A session never writes into a log it did not create
+        let third = store.begin("run", dir.path());
+        assert_ne!(third.info.id, second.info.id);
+        fs::write(dir.path().join("sessions").join(format!("{}.jsonl", third.info.id)), "").unwrap();
+        assert!(third.record_done(&log.operations).is_err());
+    }
+
+    #[test]
+    fn test_recover_settles_interrupted_moves() {
+        let dir = tempdir().unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        let op = |name: &str| Operation {
+            from: dir.path().join(name),
+            to: dir.path().join("texts").join(name),
+            op_type: OpType::Move,
+            rule_name: Some("Text".to_string()),
+        };
+        fs::create_dir(dir.path().join("texts")).unwrap();
+        // Crash after the move: only the intent is journaled
+        fs::write(dir.path().join("texts/moved.txt"), "moved").unwrap();
+        let moved = session.intent(&op("moved.txt")).unwrap();
+        // Crash halfway through copying across file systems
+        fs::write(dir.path().join("cut.txt"), "the whole file").unwrap();
+        fs::write(dir.path().join("texts/cut.txt"), "the wh").unwrap();
+        let cut = session.intent(&op("cut.txt")).unwrap();
+        // A finished operation needs no recovery
+        fs::write(dir.path().join("done.txt"), "done").unwrap();
+        let done = session.intent(&op("done.txt")).unwrap();
+        session.commit(done, &[op("done.txt")]).unwrap();
+
+        let log = store.load(&session.info.id).unwrap();
+        assert_eq!(log.pending.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![moved, cut]);
+        assert_eq!(log.operations.len(), 1);
+
+        for (id, op) in &log.pending {
+            match settle(op, &[], log.info.started).unwrap() {
+                Outcome::Completed(ops) => store.commit(&log.info.id, *id, &ops).unwrap(),
+                Outcome::RolledBack => store.abort(&log.info.id, *id).unwrap(),
+                Outcome::Manual(reason) => panic!("unexpected manual recovery: {}", reason),
+            }
+        }
+        assert!(!dir.path().join("texts/cut.txt").exists());
+        assert_eq!(fs::read_to_string(dir.path().join("cut.txt")).unwrap(), "the whole file");
+
+        let log = store.load(&session.info.id).unwrap();
+        assert!(log.pending.is_empty());
+        let recorded: Vec<_> = log.operations.iter().map(|op| op.from.clone()).collect();
+        assert_eq!(recorded, vec![dir.path().join("done.txt"), dir.path().join("moved.txt")]);
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_undo_removes_the_directories_an_extraction_created() {
+        use std::io::Write;
+
+        let dir = tempdir().unwrap();
+        let mut zip = zip::ZipWriter::new(fs::File::create(dir.path().join("photos.zip")).unwrap());
+        for (name, data) in [("a.jpg", "a"), ("trip/day1/b.jpg", "b")] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        fs::create_dir(dir.path().join("unpacked")).unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Zips"
+            extensions = ["zip"]
+            action = "extract"
+            target = "unpacked/${name}"
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new(config, dir.path().to_path_buf()).unwrap();
+        engine.execute(Some(&session), |_, _, _| {}).unwrap();
+        assert!(dir.path().join("unpacked/photos/trip/day1/b.jpg").is_file());
+
+        let log = store.load(&session.info.id).unwrap();
+        let summary = undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        // Only what existed before the run is left
+        assert!(!dir.path().join("unpacked/photos").exists());
+        assert!(dir.path().join("unpacked").is_dir());
+        assert!(dir.path().join("photos.zip").is_file());
+
+        // Extractions cannot be redone file by file, so the redo is refused as a whole
+        let undo = store.sessions().unwrap().pop().unwrap();
+        let redo = store.begin("redo", dir.path());
+        let err = redo_session(&store, &engine, &undo, &redo).err().expect("the redo must be refused");
+        assert!(err.to_string().contains("photos.zip"), "{}", err);
+        assert!(!dir.path().join("unpacked/photos").exists());
+        assert!(store.load(&undo.info.id).unwrap().undone.is_none());
+    }
+
+    #[test]
+    fn test_recover_removes_what_an_interrupted_extraction_created() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("photos.zip"), "not really a zip").unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        let unpacked = dir.path().join("unpacked");
+        let op = Operation {
+            from: dir.path().join("photos.zip"),
+            to: unpacked.clone(),
+            op_type: OpType::Extract,
+            rule_name: None,
+        };
+        // Crash while the second file was being written
+        let id = session.intent(&op).unwrap();
+        for (path, is_dir) in [(&unpacked, true), (&unpacked.join("a.jpg"), false), (&unpacked.join("b.jpg"), false)] {
+            session.created(id, path).unwrap();
+            match is_dir {
+                true => fs::create_dir(path).unwrap(),
+                false => fs::write(path, "jpg").unwrap(),
+            }
+        }
+
+        let log = store.load(&session.info.id).unwrap();
+        let (op_id, pending) = &log.pending[0];
+        let created = &log.created[op_id];
+        assert_eq!(created.len(), 3);
+        assert!(matches!(settle(pending, created, log.info.started).unwrap(), Outcome::RolledBack));
+        assert!(!unpacked.exists());
+        assert!(dir.path().join("photos.zip").is_file());
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_recover_checks_the_archive_member_name() {
+        use crate::archive::{self, named_after, MemberCache};
+
+        assert!(named_after("a.txt", "a.txt") && named_after("a (2).txt", "a.txt") && named_after("README (1)", "README"));
+        assert!(!named_after("b.txt", "a.txt") && !named_after("a ().txt", "a.txt") && !named_after("a (x).txt", "a.txt"));
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("other.txt"), "other").unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let archive_path = dir.path().join("old.zip");
+        archive::append(&archive_path, &dir.path().join("other.txt"), &MemberCache::default()).unwrap();
+        let op = Operation {
+            from: dir.path().join("a.txt"),
+            to: archive_path.clone(),
+            op_type: OpType::Archive { archive: archive_path.clone(), member: String::new() },
+            rule_name: None,
+        };
+        // The file is gone, but the last member is not it
+        fs::remove_file(&op.from).unwrap();
+        assert!(matches!(settle(&op, &[], chrono::Local::now()).unwrap(), Outcome::Manual(_)));
+
+        fs::write(&op.from, "a").unwrap();
+        assert!(matches!(settle(&op, &[], chrono::Local::now()).unwrap(), Outcome::RolledBack));
+        archive::append(&archive_path, &op.from, &MemberCache::default()).unwrap();
+        match settle(&op, &[], chrono::Local::now()).unwrap() {
+            Outcome::Completed(ops) => assert_eq!(ops[0].op_type, OpType::Archive { archive: archive_path, member: "a.txt".to_string() }),
+            _ => panic!("the archived copy was not recognized"),
+        }
+        assert!(!op.from.exists());
+    }
+
+    #[test]
+    fn test_filtered_undo_reverts_only_the_selected_operations_once() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "a.txt").unwrap();
+        fs::write(dir.path().join("b.txt"), "b.txt").unwrap();
+        fs::write(dir.path().join("c.log"), "c.log").unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            hash_cache = false
+
+            [[rules]]
+            name = "Text"
+            extensions = ["txt"]
+            target = "texts"
+
+            [[rules]]
+            name = "Logs"
+            extensions = ["log"]
+            target = "logs"
+            "#,
+        )
+        .unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        Engine::new(config, dir.path().to_path_buf()).unwrap().execute(Some(&session), |_, _, _| {}).unwrap();
+
+        let filter = UndoFilter::new(vec!["Text".to_string()], None, Some("b*"), None).unwrap();
+        let log = store.load(&session.info.id).unwrap();
+        let summary = undo_session(&store, &log, &filter, false).unwrap();
+        assert_eq!((summary.selected, summary.reverted), (1, 1));
+        assert!(dir.path().join("b.txt").exists());
+        assert!(dir.path().join("texts/a.txt").exists());
+
+        // Already undone operations are not selected again
+        let log = store.load(&session.info.id).unwrap();
+        assert_eq!(log.remaining(), 2);
+        assert_eq!(undo_session(&store, &log, &filter, false).unwrap().selected, 0);
+
+        let summary = undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert_eq!(summary.reverted, 2);
+        assert!(dir.path().join("c.log").exists());
+        assert!(store.load(&session.info.id).unwrap().undone.is_some());
+
+        assert_eq!(parse_indices("3, 5-7").unwrap(), vec![3..=3, 5..=7]);
+        assert!(parse_indices("0").is_err());
+        assert!(parse_indices("7-3").is_err());
+        // Checked against the session instead of being expanded
+        let filter = UndoFilter::new(Vec::new(), None, None, Some("1-99999999999")).unwrap();
+        assert!(filter.select(&log.operations).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filtered_undo_takes_the_duplicates_of_an_original_along() {
+        for mode in ["delete", "symlink", "hardlink"] {
+            let dir = tempdir().unwrap();
+            fs::write(dir.path().join("a.txt"), "same").unwrap();
+            fs::write(dir.path().join("b.txt"), "same").unwrap();
+            let store = JournalStore::open(dir.path().join("sessions"));
+            let session = store.begin("run", dir.path());
+            let config: Config = toml::from_str(&format!(
+                r#"
+                hash_cache = false
+                dedup = "{}"
+
+                [[rules]]
+                name = "Text"
+                extensions = ["txt"]
+                target = "texts"
+                "#,
+                mode
+            ))
+            .unwrap();
+            Engine::new(config, dir.path().to_path_buf()).unwrap().execute(Some(&session), |_, _, _| {}).unwrap();
+
+            let log = store.load(&session.info.id).unwrap();
+            let filter = UndoFilter::new(Vec::new(), None, Some("a*"), None).unwrap();
+            assert_eq!(filter.select(&log.operations).unwrap().len(), 2, "{}", mode);
+            let summary = undo_session(&store, &log, &filter, false).unwrap();
+            assert!(summary.failed.is_empty(), "{}: {:?}", mode, summary.failed);
+            assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "same");
+            assert_eq!(fs::read_to_string(dir.path().join("b.txt")).unwrap(), "same");
+            assert!(!same_file(&dir.path().join("a.txt"), &dir.path().join("b.txt")));
+
+            // Nothing is left that a later undo could trip over
+            let log = store.load(&session.info.id).unwrap();
+            assert_eq!(log.remaining(), 0);
+            assert!(dir.path().join("texts").read_dir().unwrap().next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_redo_reapplies_an_undo_with_conflict_checks() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        fs::write(dir.path().join("b.txt"), "b").unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let run = store.begin("run", dir.path());
+        let engine = Engine::new(text_rule(), dir.path().to_path_buf()).unwrap();
+        engine.execute(Some(&run), |_, _, _| {}).unwrap();
+
+        let log = store.load(&run.info.id).unwrap();
+        undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        let undo = store.sessions().unwrap().pop().unwrap();
+        assert_eq!(undo.info.undoes.as_deref(), Some(run.info.id.as_str()));
+        assert_eq!(undo.operations.len(), 2);
+
+        // The target was taken in the meantime, so the rule's default strategy renames
+        fs::write(dir.path().join("texts/a.txt"), "other").unwrap();
+        let redo = store.begin("redo", dir.path());
+        let summary = redo_session(&store, &engine, &undo, &redo).unwrap();
+        assert_eq!(summary.redone, 2);
+        assert!(summary.problems.is_empty());
+        assert_eq!(fs::read_to_string(dir.path().join("texts/a (1).txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.path().join("texts/a.txt")).unwrap(), "other");
+        assert!(store.load(&undo.info.id).unwrap().undone.is_some());
+
+        // The redo is an ordinary session that can be undone again
+        let log = store.load(&redo.info.id).unwrap();
+        assert_eq!(log.operations.len(), 2);
+        undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert!(dir.path().join("a.txt").exists());
+        assert!(dir.path().join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_undo_preview_reports_problems_and_undo_carries_on_past_them() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "a.txt").unwrap();
+        fs::write(dir.path().join("b.txt"), "b.txt").unwrap();
+        fs::write(dir.path().join("c.txt"), "c.txt").unwrap();
+        fs::write(dir.path().join("d.txt"), "d.txt").unwrap();
+        let store = JournalStore::open(dir.path().join("sessions"));
+        let session = store.begin("run", dir.path());
+        Engine::new(text_rule(), dir.path().to_path_buf()).unwrap().execute(Some(&session), |_, _, _| {}).unwrap();
+
+        fs::remove_file(dir.path().join("texts/a.txt")).unwrap();
+        fs::write(dir.path().join("texts/b.txt"), "edited since").unwrap();
+        fs::write(dir.path().join("c.txt"), "new file in the way").unwrap();
+        let log = store.load(&session.info.id).unwrap();
+        let checks: Vec<(String, &str)> = log
+            .operations
+            .iter()
+            .zip(&log.fingerprints)
+            .map(|(op, fingerprint)| {
+                let check = inspect(op, fingerprint.as_ref(), false).unwrap();
+                (op.from.file_name().unwrap().to_string_lossy().to_string(), check.label())
+            })
+            .collect();
+        for (name, label) in [("a.txt", "missing"), ("b.txt", "modified"), ("c.txt", "collision"), ("d.txt", "restore")] {
+            assert!(checks.contains(&(name.to_string(), label)), "{} should be {}: {:?}", name, label, checks);
+        }
+        let c = log.operations.iter().find(|op| op.from.ends_with("c.txt")).unwrap();
+        assert!(matches!(inspect(c, None, true).unwrap(), Check::Collision(detail) if detail.contains("overwritten")));
+
+        let summary = undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert_eq!((summary.reverted, summary.unchanged, summary.failed.len()), (2, 1, 1));
+        assert_eq!(summary.failed[0].0, dir.path().join("c.txt"));
+        assert_eq!(fs::read_to_string(dir.path().join("b.txt")).unwrap(), "edited since");
+        assert!(dir.path().join("d.txt").exists());
+
+        // The failed operation stays in the journal for another attempt
+        fs::remove_file(dir.path().join("c.txt")).unwrap();
+        let log = store.load(&session.info.id).unwrap();
+        assert_eq!(log.remaining(), 1);
+        let summary = undo_session(&store, &log, &UndoFilter::default(), false).unwrap();
+        assert_eq!(summary.reverted, 1);
+        assert!(store.load(&session.info.id).unwrap().undone.is_some());
+    }
+}